@@ -0,0 +1,4 @@
+//! Code shared by the server and the `dev_server` proxy.
+
+pub mod shutdown;
+pub mod tracing;
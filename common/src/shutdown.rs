@@ -0,0 +1,139 @@
+//! Graceful shutdown, as the server and the proxy do it: on `SIGINT` or `SIGTERM`, stop accepting
+//! connections, tell long-lived responses to end, and give the requests in flight a deadline to
+//! complete. How long that is depends on the binary.
+//!
+//! The graceful shutdown of axum stops tracking a connection once it has been upgraded, so
+//! WebSocket sessions must be [tracked](Shutdown::track) on their own. Otherwise the process could
+//! exit before their close frames are sent.
+
+use ::std::future::Future;
+use ::std::sync::Arc;
+use ::std::time::Duration;
+use ::tokio::sync::watch;
+use ::tokio_util::task::task_tracker::TrackedFuture;
+use ::tokio_util::task::TaskTracker;
+use ::tracing::{error, info};
+
+/// Tells long-lived responses that the process is shutting down, and keeps track of the sessions
+/// that outlive their request.
+#[derive(Clone)]
+pub struct Shutdown {
+    triggered: Arc<watch::Sender<bool>>,
+    sessions: TaskTracker,
+}
+
+impl Shutdown {
+    /// Returns a `Shutdown` that is only triggered by [`Shutdown::trigger`].
+    pub fn new() -> Self {
+        Shutdown {
+            triggered: Arc::new(watch::channel(false).0),
+            sessions: TaskTracker::new(),
+        }
+    }
+
+    /// Returns a `Shutdown` that is triggered by `SIGINT` or `SIGTERM`.
+    pub fn on_signal() -> Self {
+        let shutdown = Shutdown::new();
+        tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                signal().await;
+                shutdown.trigger();
+            }
+        });
+        shutdown
+    }
+
+    pub fn trigger(&self) {
+        self.triggered.send_replace(true);
+    }
+
+    /// Completes once the shutdown has been triggered, or right away if it already has.
+    pub async fn triggered(&self) {
+        // The sender is alive as long as `self` is, so this cannot fail.
+        let _ = self
+            .triggered
+            .subscribe()
+            .wait_for(|&triggered| triggered)
+            .await;
+    }
+
+    /// Completes `drain` after the shutdown has been triggered.
+    pub async fn deadline(&self, drain: Duration) {
+        self.triggered().await;
+        tokio::time::sleep(drain).await;
+    }
+
+    /// Wraps a session that outlives its request, such as a WebSocket connection, so that
+    /// [`Shutdown::sessions_ended`] waits for it.
+    pub fn track<F: Future>(&self, session: F) -> TrackedFuture<F> {
+        self.sessions.track_future(session)
+    }
+
+    /// Completes once every tracked session has ended. Call it when the server no longer accepts
+    /// connections, so that no session can start afterwards.
+    pub async fn sessions_ended(&self) {
+        self.sessions.close();
+        self.sessions.wait().await;
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown::new()
+    }
+}
+
+/// Completes on Ctrl+C, or on `SIGTERM`, which container runtimes and `start_dev_server.sh` send
+/// to stop a process.
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Cannot listen for Ctrl+C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("Cannot listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        () = ctrl_c => info!("Received Ctrl+C, shutting down gracefully"),
+        () = terminate => info!("Received SIGTERM, shutting down gracefully"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_for_tracked_sessions() {
+        let shutdown = Shutdown::new();
+        let (end_session, session_ended) = tokio::sync::oneshot::channel::<()>();
+        let session = shutdown.track(async move {
+            let _ = session_ended.await;
+        });
+        tokio::spawn(session);
+        shutdown.trigger();
+
+        let sessions_ended = shutdown.sessions_ended();
+        tokio::pin!(sessions_ended);
+        let wait = Duration::from_millis(50);
+        assert!(tokio::time::timeout(wait, &mut sessions_ended)
+            .await
+            .is_err());
+        end_session.send(()).unwrap();
+        tokio::time::timeout(wait, sessions_ended).await.unwrap();
+    }
+}
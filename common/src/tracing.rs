@@ -0,0 +1,198 @@
+//! The parts of the tracing setup that the server and the proxy have in common: the log filter
+//! that `/admin/log-level` and `/__dev_server/log-level` change at runtime, the OTLP export, and
+//! the propagation of W3C trace context in HTTP headers.
+
+use ::axum::http::{HeaderMap, HeaderName, HeaderValue};
+use ::opentelemetry::propagation::{Extractor, Injector};
+use ::opentelemetry::trace::TracerProvider as _;
+use ::opentelemetry::KeyValue;
+use ::opentelemetry_otlp::WithExportConfig;
+use ::opentelemetry_sdk::{runtime, trace as sdktrace, Resource};
+use ::serde::Serialize;
+use ::std::sync::{Arc, Mutex};
+use ::std::time::{Duration, Instant};
+use ::tracing::{info, warn, Subscriber};
+use ::tracing_opentelemetry::OpenTelemetryLayer;
+use ::tracing_subscriber::registry::LookupSpan;
+use ::tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// Reads and replaces the directives of the log filter while the process is running.
+#[derive(Clone)]
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    /// The directives at startup (`RUST_LOG`, or the default of the binary).
+    initial_directives: Arc<str>,
+    state: Arc<Mutex<LogFilterState>>,
+}
+
+#[derive(Default)]
+struct LogFilterState {
+    /// When the directives revert to the initial ones, if they have been changed temporarily.
+    reverts_at: Option<Instant>,
+    /// Counts changes, so that a pending revert can tell that it has been superseded.
+    generation: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogFilterStatus {
+    directives: String,
+    initial_directives: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reverts_in_seconds: Option<u64>,
+}
+
+impl LogFilter {
+    /// Returns the filter layer, to be stacked right onto the registry, and its `LogFilter`.
+    pub fn new(filter: EnvFilter) -> (reload::Layer<EnvFilter, Registry>, Self) {
+        let initial_directives = filter.to_string();
+        let (layer, handle) = reload::Layer::new(filter);
+        let log_filter = LogFilter {
+            handle,
+            initial_directives: initial_directives.into(),
+            state: Arc::default(),
+        };
+        (layer, log_filter)
+    }
+
+    pub fn status(&self) -> anyhow::Result<LogFilterStatus> {
+        let state = self.state.lock().unwrap();
+        Ok(LogFilterStatus {
+            directives: self.handle.with_current(EnvFilter::to_string)?,
+            initial_directives: self.initial_directives.to_string(),
+            reverts_in_seconds: state.reverts_at.map(|reverts_at| {
+                let remaining = reverts_at.saturating_duration_since(Instant::now());
+                remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0)
+            }),
+        })
+    }
+
+    /// Replaces the filter. After `ttl` (if given), the initial directives are restored.
+    pub fn set(&self, filter: EnvFilter, ttl: Option<Duration>) -> anyhow::Result<()> {
+        let directives = filter.to_string();
+        let mut state = self.state.lock().unwrap();
+        self.handle.reload(filter)?;
+        state.generation += 1;
+        state.reverts_at = ttl.map(|ttl| Instant::now() + ttl);
+        info!(
+            directives,
+            ttl_seconds = ttl.map(|ttl| ttl.as_secs()),
+            "Log filter changed"
+        );
+        if let Some(ttl) = ttl {
+            let log_filter = self.clone();
+            let generation = state.generation;
+            tokio::spawn(async move {
+                tokio::time::sleep(ttl).await;
+                log_filter.restore(Some(generation));
+            });
+        }
+        Ok(())
+    }
+
+    /// Restores the initial directives.
+    pub fn revert(&self) {
+        self.restore(None);
+    }
+
+    /// Restores the initial directives. If `generation` is given, only if the filter has not been
+    /// changed since.
+    fn restore(&self, generation: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        if generation.is_some_and(|generation| generation != state.generation) {
+            return;
+        }
+        // The initial directives have been parsed before.
+        let filter = EnvFilter::new(&*self.initial_directives);
+        if let Err(e) = self.handle.reload(filter) {
+            warn!("Cannot restore the log filter: {e}");
+            return;
+        }
+        state.generation += 1;
+        state.reverts_at = None;
+        info!(
+            directives = &*self.initial_directives,
+            "Log filter restored"
+        );
+    }
+}
+
+/// Returns a layer that exports spans with OTLP over gRPC, if `OTEL_EXPORTER_OTLP_ENDPOINT` is
+/// set, and the [`OtlpExport`] to shut down at exit. The service is reported as
+/// `default_service_name`, unless `OTEL_SERVICE_NAME` is set. Any OTLP collector will do for local
+/// testing, e.g. Jaeger:
+///
+/// ```sh
+/// docker run --rm -p 4317:4317 -p 16686:16686 jaegertracing/all-in-one
+/// OTEL_EXPORTER_OTLP_ENDPOINT=http://localhost:4317 cargo shuttle run
+/// ```
+pub fn otlp_layer<S>(
+    default_service_name: &str,
+) -> anyhow::Result<Option<(OpenTelemetryLayer<S, sdktrace::Tracer>, OtlpExport)>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let Ok(endpoint) = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") else {
+        return Ok(None);
+    };
+    let service_name =
+        std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| default_service_name.to_string());
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint)
+        .build_span_exporter()?;
+    let provider = sdktrace::TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_config(
+            sdktrace::config()
+                .with_resource(Resource::new([KeyValue::new("service.name", service_name)])),
+        )
+        .build();
+    let tracer = provider.tracer("opentelemetry-otlp");
+    let layer = tracing_opentelemetry::layer().with_tracer(tracer);
+    Ok(Some((layer, OtlpExport(provider))))
+}
+
+/// The OTLP export of spans. Spans are sent in batches, every few seconds, so shut the export down
+/// once the process has stopped serving. Otherwise the spans of the last requests are lost.
+#[must_use]
+pub struct OtlpExport(sdktrace::TracerProvider);
+
+impl OtlpExport {
+    /// Exports the spans that are still buffered, and stops the export.
+    pub async fn shutdown(self) {
+        let OtlpExport(provider) = self;
+        // The tracer only holds a weak reference, so this drops the provider, which shuts it
+        // down. That blocks until the batch task, which runs on the runtime, has exported.
+        if let Err(e) = tokio::task::spawn_blocking(move || drop(provider)).await {
+            warn!("Cannot export the last spans: {e}");
+        }
+    }
+}
+
+/// Reads trace context from the headers of a request.
+pub struct HeaderExtractor<'a>(pub &'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key)?.to_str().ok()
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+/// Writes trace context into the headers of a request.
+pub struct HeaderInjector<'a>(pub &'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
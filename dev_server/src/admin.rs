@@ -0,0 +1,61 @@
+//! Endpoints that the proxy answers itself, instead of forwarding them. The proxy only listens on
+//! localhost, so they are not protected.
+//!
+//! `/__dev_server/log-level` works like `/admin/log-level` of the server (see
+//! `server/src/route/admin.rs`), but for the proxy's own log filter.
+
+use ::axum::extract::rejection::JsonRejection;
+use ::axum::extract::State;
+use ::axum::http::StatusCode;
+use ::axum::routing::get;
+use ::axum::{Json, Router};
+use ::common::tracing::{LogFilter, LogFilterStatus};
+use ::serde::Deserialize;
+use ::std::time::Duration;
+use ::tracing_subscriber::EnvFilter;
+
+pub(crate) fn router(log_filter: LogFilter) -> Router<()> {
+    Router::new()
+        .route(
+            "/__dev_server/log-level",
+            get(get_log_level)
+                .put(put_log_level)
+                .delete(delete_log_level),
+        )
+        .with_state(log_filter)
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct LogLevelRequest {
+    directives: String,
+    ttl_seconds: Option<u64>,
+}
+
+type LogLevelResponse = Result<Json<LogFilterStatus>, (StatusCode, String)>;
+
+async fn get_log_level(State(log_filter): State<LogFilter>) -> LogLevelResponse {
+    log_filter.status().map(Json).map_err(internal_error)
+}
+
+async fn put_log_level(
+    State(log_filter): State<LogFilter>,
+    request: Result<Json<LogLevelRequest>, JsonRejection>,
+) -> LogLevelResponse {
+    let Json(request) = request.map_err(|e| (e.status(), e.body_text()))?;
+    let filter = EnvFilter::try_new(&request.directives)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid directives: {e}")))?;
+    log_filter
+        .set(filter, request.ttl_seconds.map(Duration::from_secs))
+        .map_err(internal_error)?;
+    get_log_level(State(log_filter)).await
+}
+
+async fn delete_log_level(State(log_filter): State<LogFilter>) -> LogLevelResponse {
+    log_filter.revert();
+    get_log_level(State(log_filter)).await
+}
+
+fn internal_error(e: anyhow::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:#}"))
+}
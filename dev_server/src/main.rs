@@ -4,6 +4,7 @@
 
 use ::axum::body::Body as AxumBody;
 use ::axum::extract;
+use ::axum::extract::FromRef;
 use ::axum::extract::FromRequestParts;
 use ::axum::extract::State;
 use ::axum::extract::WebSocketUpgrade;
@@ -32,8 +33,15 @@ use ::tokio::net::TcpStream;
 use ::tokio_tungstenite::MaybeTlsStream;
 use ::tracing::error;
 use ::tracing::info;
+use ::tracing::warn;
+use ::tracing::Span;
 use tokio_tungstenite::tungstenite;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
 
+use crate::shutdown::{Shutdown, DRAIN_DEADLINE};
+
+mod admin;
+mod shutdown;
 mod tracing;
 
 const BIND_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8080);
@@ -48,13 +56,32 @@ lazy_static! {
 
 type Client = hyper_util::client::legacy::Client<HttpConnector, AxumBody>;
 
+#[derive(Clone)]
+struct AppState {
+    client: Client,
+    shutdown: Shutdown,
+}
+
+impl FromRef<AppState> for Client {
+    fn from_ref(state: &AppState) -> Self {
+        state.client.clone()
+    }
+}
+
+impl FromRef<AppState> for Shutdown {
+    fn from_ref(state: &AppState) -> Self {
+        state.shutdown.clone()
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing::init();
+    let (log_filter, otlp_export) = tracing::init();
 
     let client: Client =
         hyper_util::client::legacy::Client::<(), ()>::builder(TokioExecutor::new())
             .build(HttpConnector::new());
+    let shutdown = Shutdown::on_signal();
 
     let router = Router::new()
         .route("/api/*_", get(proxy_to_rust))
@@ -63,62 +90,103 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .route("/oauth/*_", post(proxy_to_rust))
         .route("/*_", get(proxy_to_elm))
         .route("/", get(proxy_to_elm))
-        .with_state(client);
+        .with_state(AppState {
+            client,
+            shutdown: shutdown.clone(),
+        })
+        .merge(admin::router(log_filter));
 
     let listener = TcpListener::bind(BIND_ADDR).await?;
     info!("listening on {}", listener.local_addr().unwrap());
     let router = tracing::wrap_router(router);
-    Ok(axum::serve(listener, router.into_make_service()).await?)
+    let server = axum::serve(listener, router.into_make_service()).with_graceful_shutdown({
+        let shutdown = shutdown.clone();
+        async move { shutdown.triggered().await }
+    });
+    // Upgraded connections are not awaited by the server, see `common::shutdown`.
+    let drained = async {
+        server.await?;
+        shutdown.sessions_ended().await;
+        Ok::<_, std::io::Error>(())
+    };
+    tokio::select! {
+        result = drained => {
+            result?;
+            info!("shut down");
+        }
+        () = shutdown.deadline(DRAIN_DEADLINE) => warn!(
+            "requests still in flight after {}s, shutting down anyway",
+            DRAIN_DEADLINE.as_secs()
+        ),
+    }
+    if let Some(otlp_export) = otlp_export {
+        otlp_export.shutdown().await;
+    }
+    Ok(())
 }
 
-async fn proxy_to_elm(State(client): State<Client>, req: extract::Request) -> AxumResponse {
-    proxy_to(&ELM_AUTHORITY, req, client).await
+async fn proxy_to_elm(
+    State(client): State<Client>,
+    State(shutdown): State<Shutdown>,
+    req: extract::Request,
+) -> AxumResponse {
+    proxy_to(&ELM_AUTHORITY, req, client, shutdown).await
 }
 
-async fn proxy_to_rust(State(client): State<Client>, req: extract::Request) -> AxumResponse {
-    proxy_to(&RUST_AUTHORITY, req, client).await
+async fn proxy_to_rust(
+    State(client): State<Client>,
+    State(shutdown): State<Shutdown>,
+    req: extract::Request,
+) -> AxumResponse {
+    proxy_to(&RUST_AUTHORITY, req, client, shutdown).await
 }
 
 async fn proxy_to(
     new_authority: &Authority,
     req: extract::Request,
     client: Client,
+    shutdown: Shutdown,
 ) -> AxumResponse {
     let original_uri = req.uri().clone(); // cloning avoids borrowing of req
     let new_uri = replace_authority(&original_uri, new_authority);
 
     // Attempt to upgrade to WebSocket
     let (mut parts, body) = req.into_parts();
+    tracing::inject_trace_context(&Span::current(), &mut parts.headers);
     if let Ok(ws_upgrade) = WebSocketUpgrade::from_request_parts(&mut parts, &()).await {
         info!("Attempting to upgrade WebSocket connection");
         let new_uri = replace_scheme(&new_uri, "ws");
+        // Tracked, so that the proxy waits for the close frames on shutdown
+        let sessions = shutdown.clone();
         ws_upgrade
             .on_failed_upgrade(|err| {
                 error!("Error upgrading WebSocket: {err}");
             })
-            .on_upgrade(|upstream_ws| async move {
-                info!("Connection upgraded, now connecting to {new_uri}");
-                // Reconstruct the request with the new URI. Note that upgrading is only supported
-                // for GET requests, so we don't need the body.
-                let mut req = extract::Request::from_parts(parts, ());
-                *req.uri_mut() = new_uri.clone();
-                match tokio::time::timeout(
-                    Duration::from_millis(1000),
-                    tokio_tungstenite::connect_async(req),
-                )
-                .await
-                {
-                    Ok(no_timeout) => match no_timeout {
-                        Ok((downstream_ws, ws_connect_response)) => {
-                            info!("Upgraded WebSocket connection");
-                            let status = ws_connect_response.status();
-                            info!("(status: {status})");
-                            connect_websockets(upstream_ws, downstream_ws).await;
-                        }
+            .on_upgrade(move |upstream_ws| {
+                sessions.track(async move {
+                    info!("Connection upgraded, now connecting to {new_uri}");
+                    // Reconstruct the request with the new URI. Note that upgrading is only supported
+                    // for GET requests, so we don't need the body.
+                    let mut req = extract::Request::from_parts(parts, ());
+                    *req.uri_mut() = new_uri.clone();
+                    match tokio::time::timeout(
+                        Duration::from_millis(1000),
+                        tokio_tungstenite::connect_async(req),
+                    )
+                    .await
+                    {
+                        Ok(no_timeout) => match no_timeout {
+                            Ok((downstream_ws, ws_connect_response)) => {
+                                info!("Upgraded WebSocket connection");
+                                let status = ws_connect_response.status();
+                                info!("(status: {status})");
+                                connect_websockets(upstream_ws, downstream_ws, shutdown).await;
+                            }
+                            Err(err) => error!("Error connecting to {new_uri}: {err}"),
+                        },
                         Err(err) => error!("Error connecting to {new_uri}: {err}"),
-                    },
-                    Err(err) => error!("Error connecting to {new_uri}: {err}"),
-                }
+                    }
+                })
             })
     } else {
         // Forward the request
@@ -155,6 +223,7 @@ fn replace_scheme(uri: &Uri, new_scheme: &str) -> http::Uri {
 async fn connect_websockets(
     client: axum::extract::ws::WebSocket,
     server: tokio_tungstenite::WebSocketStream<MaybeTlsStream<TcpStream>>,
+    shutdown: Shutdown,
 ) {
     enum ConnectionCtrl {
         KeepOpen,
@@ -167,12 +236,41 @@ async fn connect_websockets(
         if let ConnectionCtrl::Close = tokio::select! {
             maybe_next = server.next() => try_forward("server", maybe_next, &mut client).await,
             maybe_next = client.next() => try_forward("client", maybe_next, &mut server).await,
+            () = shutdown.triggered() => {
+                close_going_away(&mut client, &mut server).await;
+                ConnectionCtrl::Close
+            }
         } {
             break;
         }
     }
     info!("Closing WebSocket connection");
 
+    /// Tells both ends that the proxy is going away, with close code 1001.
+    async fn close_going_away<C, S>(client: &mut C, server: &mut S)
+    where
+        C: Sink<axum::extract::ws::Message> + std::marker::Unpin,
+        <C as Sink<axum::extract::ws::Message>>::Error: std::fmt::Display,
+        S: Sink<tungstenite::Message> + std::marker::Unpin,
+        <S as Sink<tungstenite::Message>>::Error: std::fmt::Display,
+    {
+        const REASON: &str = "the dev-server is shutting down";
+        let to_client = axum::extract::ws::Message::Close(Some(axum::extract::ws::CloseFrame {
+            code: CloseCode::Away.into(),
+            reason: REASON.into(),
+        }));
+        if let Err(err) = client.send(to_client).await {
+            error!("Error closing WebSocket connection to client: {err}");
+        }
+        let to_server = tungstenite::Message::Close(Some(tungstenite::protocol::CloseFrame {
+            code: CloseCode::Away,
+            reason: REASON.into(),
+        }));
+        if let Err(err) = server.send(to_server).await {
+            error!("Error closing WebSocket connection to server: {err}");
+        }
+    }
+
     async fn try_forward<S, Msg1, Msg2, E>(
         source_name: &str,
         maybe_msg: Option<Result<Msg1, E>>,
@@ -0,0 +1,11 @@
+//! Graceful shutdown, like the server's: on `SIGINT` or `SIGTERM`, the proxy stops accepting
+//! connections and gives the requests in flight [`DRAIN_DEADLINE`] to complete. Bridged WebSocket
+//! connections are closed on both ends with code 1001 ("going away").
+
+use ::std::time::Duration;
+
+pub(crate) use ::common::shutdown::Shutdown;
+
+/// Shorter than the server's: nothing needs to be drained during development, and
+/// `start_dev_server.sh` should not keep the developer waiting.
+pub(crate) const DRAIN_DEADLINE: Duration = Duration::from_secs(10);
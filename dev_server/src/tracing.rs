@@ -1,19 +1,53 @@
+use ::axum::http::{self, HeaderMap};
+use ::axum::Router;
+use ::common::tracing::{otlp_layer, HeaderExtractor, HeaderInjector, LogFilter, OtlpExport};
+use ::opentelemetry::global;
+use ::opentelemetry::trace::TraceContextExt;
+use ::opentelemetry_sdk::propagation::TraceContextPropagator;
+use ::tower_http::trace::TraceLayer;
+use ::tracing::{info, info_span, warn, Span};
+use ::tracing_opentelemetry::OpenTelemetrySpanExt;
+use ::tracing_subscriber::EnvFilter;
 use ::tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use axum::Router;
-use tower_http::trace::TraceLayer;
 
-pub(crate) fn init() {
+/// The directives of the log filter, unless `RUST_LOG` is set.
+///
+/// axum logs rejections from built-in extractors with the `axum::rejection` target, at `TRACE`
+/// level. `axum::rejection=trace` enables showing those events. The code shared with the server
+/// logs with the target `common`.
+const DEFAULT_LOG_FILTER: &str = "dev_server=info,common=info,axum::rejection=trace";
+// const DEFAULT_LOG_FILTER: &str = "dev_server=info,common=info,tower_http=trace,axum::rejection=trace";
+
+/// Reported to the OpenTelemetry collector, unless `OTEL_SERVICE_NAME` is set.
+const DEFAULT_SERVICE_NAME: &str = "elm-on-shuttle-dev-server";
+
+/// Returns the log filter, and the OTLP export to shut down once the proxy has stopped.
+pub(crate) fn init() -> (LogFilter, Option<OtlpExport>) {
+    // The proxy forwards W3C trace context (`traceparent` and `tracestate` headers), so that
+    // browser, proxy, server and GitHub calls end up in one trace.
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let ((otlp_layer, otlp_export), otlp_error) = match otlp_layer(DEFAULT_SERVICE_NAME) {
+        Ok(otlp) => (otlp.unzip(), None),
+        Err(e) => ((None, None), Some(e)),
+    };
+    let is_exporting = otlp_layer.is_some();
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| DEFAULT_LOG_FILTER.into());
+    let (filter, log_filter) = LogFilter::new(filter);
+
     tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                // axum logs rejections from built-in extractors with the `axum::rejection`
-                // target, at `TRACE` level. `axum::rejection=trace` enables showing those events
-                "dev_server=info,axum::rejection=trace".into()
-                // "dev_server=info,tower_http=trace,axum::rejection=trace".into()
-            }),
-        )
+        .with(filter)
         .with(tracing_subscriber::fmt::layer())
+        .with(otlp_layer)
         .init();
+
+    if is_exporting {
+        info!("Exporting traces with OTLP");
+    }
+    if let Some(e) = otlp_error {
+        warn!("Cannot export traces with OTLP: {e:#}");
+    }
+    (log_filter, otlp_export)
 }
 
 pub(crate) fn wrap_router(router: Router) -> Router {
@@ -26,5 +60,44 @@ pub(crate) fn wrap_router(router: Router) -> Router {
     // It provides good defaults but is also very customizable.
     //
     // See https://docs.rs/tower-http/0.1.1/tower_http/trace/index.html for more details.
-    router.layer(TraceLayer::new_for_http())
+    router.layer(
+        TraceLayer::new_for_http().make_span_with(|request: &http::Request<_>| {
+            let span = info_span!(
+                "request",
+                method = %request.method(),
+                uri = %request.uri(),
+                trace_id = tracing::field::Empty,
+            );
+            // Continue the trace of the browser, if it sent a `traceparent` header.
+            let parent_context = global::get_text_map_propagator(|propagator| {
+                propagator.extract(&HeaderExtractor(request.headers()))
+            });
+            span.set_parent(parent_context.clone());
+            // Annotate the log with the trace ID: our own if we export spans, otherwise the one of
+            // the browser (if any).
+            let own_context = span.context();
+            let trace_context = if own_context.span().span_context().is_valid() {
+                own_context
+            } else {
+                parent_context
+            };
+            let span_context = trace_context.span().span_context().clone();
+            if span_context.is_valid() {
+                span.record("trace_id", tracing::field::display(span_context.trace_id()));
+            }
+            span
+        }),
+    )
+}
+
+/// Replaces the trace context in the headers of a forwarded request with the context of `span`,
+/// so that the server's spans become children of the proxy's span. If spans are not exported,
+/// the original headers are forwarded unchanged.
+pub(crate) fn inject_trace_context(span: &Span, headers: &mut HeaderMap) {
+    let context = span.context();
+    if context.span().span_context().is_valid() {
+        global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&context, &mut HeaderInjector(headers))
+        });
+    }
 }
@@ -0,0 +1,93 @@
+//! Embeds build information, which the server reports at `/version`, and checks that the UI is
+//! built when the `embed-ui` feature embeds it.
+
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    println!(
+        "cargo:rustc-env=GIT_COMMIT={}",
+        git_commit().unwrap_or_else(|| "unknown".to_string())
+    );
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", build_timestamp());
+
+    // Rebuild when the sources or the checked-out commit change, but not on every build.
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs/heads");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    if std::env::var_os("CARGO_FEATURE_EMBED_UI").is_some() {
+        // `include_dir!` only tracks the files that exist, not new ones.
+        println!("cargo:rerun-if-changed=../ui/dist");
+        println!("cargo:rustc-check-cfg=cfg(ui_dist_missing)");
+        if !Path::new("../ui/dist/index.html").is_file() {
+            let message =
+                "The `embed-ui` feature embeds ui/dist, which has no index.html. Run `make build_ui` first.";
+            // A release binary without its UI must not ship. A debug build (e.g. `cargo clippy
+            // --all-features` on a clean checkout) embeds no UI, and `/readyz` reports it.
+            assert!(
+                std::env::var("PROFILE").as_deref() != Ok("release"),
+                "{message}"
+            );
+            println!("cargo:warning={message}");
+            println!("cargo:rustc-cfg=ui_dist_missing");
+        }
+    }
+}
+
+/// Returns the hash of the checked-out commit, with a `-dirty` suffix if there are uncommitted
+/// changes. Returns `None` if this is not a git working copy (e.g. when building on shuttle).
+fn git_commit() -> Option<String> {
+    let output = Command::new("git")
+        .args([
+            "describe",
+            "--always",
+            "--dirty",
+            "--abbrev=12",
+            "--exclude=*",
+        ])
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|commit| !commit.is_empty())
+}
+
+/// Returns the current time (or `SOURCE_DATE_EPOCH`, for reproducible builds) in RFC 3339 format.
+fn build_timestamp() -> String {
+    let seconds = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs())
+        });
+    let (days, seconds_of_day) = (seconds / 86_400, seconds % 86_400);
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
+
+/// Converts days since 1970-01-01 into a (year, month, day) date. This is Howard Hinnant's
+/// `civil_from_days` algorithm, see https://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,166 @@
+use ::axum::async_trait;
+use ::axum::extract::{FromRef, FromRequestParts};
+use ::axum::http::request::Parts;
+use ::axum::http::{header, HeaderMap, Method, StatusCode};
+use ::serde::Deserialize;
+use ::std::collections::HashMap;
+use ::std::sync::{Arc, Mutex};
+use ::std::time::{Duration, Instant};
+use ::tracing::{debug, Span};
+
+use crate::http_client::{Endpoint, HttpClient, Upstream};
+use crate::redact::SecretString;
+use crate::route::api::ApiError;
+
+/// Name of the cookie in which the OAuth callback stores the GitHub user access token.
+pub(crate) const ACCESS_TOKEN_COOKIE: &str = "github-access-token";
+
+const GITHUB_USER: Endpoint = Endpoint {
+    name: "user",
+    upstream: Upstream::GitHubApi,
+    method: Method::GET,
+    path: "/user",
+};
+
+/// How long a resolved identity is trusted before GitHub is asked again.
+const IDENTITY_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// The signed-in GitHub user on whose behalf a request is made.
+///
+/// Use this as an extractor in any handler that requires authentication. Requests without a valid
+/// GitHub user access token are rejected with `401 Unauthorized`.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct User {
+    /// The numeric GitHub user ID. Unlike the login, it never changes.
+    pub id: u64,
+    pub login: String,
+}
+
+/// Resolves GitHub user access tokens to [`User`]s, and caches the result.
+#[derive(Clone)]
+pub(crate) struct Authenticator {
+    client: HttpClient,
+    cache: Arc<Mutex<HashMap<String, (User, Instant)>>>,
+}
+
+impl Authenticator {
+    pub(crate) fn new(client: HttpClient) -> Self {
+        Self {
+            client,
+            cache: Arc::default(),
+        }
+    }
+
+    async fn resolve(&self, token: &str) -> Result<User, ApiError> {
+        if let Some(user) = self.verified_user(token) {
+            return Ok(user);
+        }
+        let url = self.client.url(&GITHUB_USER);
+        let response = self
+            .client
+            .send(&GITHUB_USER, |request| {
+                request
+                    .bearer_auth(token)
+                    .header(header::ACCEPT, "application/vnd.github+json")
+            })
+            .await
+            .map_err(|e| {
+                if e.is_timeout() {
+                    ApiError::UpstreamTimeout(format!("{url} did not respond in time"))
+                } else {
+                    ApiError::Upstream(format!("Cannot connect to {url}: {e}"))
+                }
+            })?;
+        match response.status() {
+            status if status.is_success() => {
+                let user = response.json::<User>().await.map_err(|e| {
+                    if e.is_timeout() {
+                        ApiError::UpstreamTimeout(format!("{url} did not respond in time"))
+                    } else {
+                        ApiError::Upstream(format!("Unexpected response from {url}: {e}"))
+                    }
+                })?;
+                debug!(user.id, user.login, "Resolved GitHub identity");
+                self.remember(token, user.clone());
+                Ok(user)
+            }
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(ApiError::Unauthorized),
+            status => Err(ApiError::Upstream(format!("{url} responded with {status}"))),
+        }
+    }
+
+    /// Caches `user` as the owner of `token`, for [`IDENTITY_CACHE_TTL`].
+    pub(crate) fn remember(&self, token: &str, user: User) {
+        self.cache.lock().unwrap().insert(
+            token.to_string(),
+            (user, Instant::now() + IDENTITY_CACHE_TTL),
+        );
+    }
+
+    /// Whether `token` has been resolved to a user recently. Does not ask GitHub.
+    pub(crate) fn is_verified(&self, token: &str) -> bool {
+        self.verified_user(token).is_some()
+    }
+
+    /// The user to whom `token` has been resolved recently, if any. Does not ask GitHub.
+    pub(crate) fn verified_user(&self, token: &str) -> Option<User> {
+        let mut cache = self.cache.lock().unwrap();
+        let now = Instant::now();
+        cache.retain(|_, (_, expires_at)| *expires_at > now);
+        cache.get(token).map(|(user, _)| user.clone())
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for User
+where
+    Authenticator: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let token = access_token(&parts.headers).ok_or(ApiError::Unauthorized)?;
+        let user = Authenticator::from_ref(state).resolve(token).await?;
+        Span::current().record("user_id", user.id);
+        Ok(user)
+    }
+}
+
+/// Returns the GitHub user access token of a request, taken from an `Authorization: Bearer` header
+/// or, for requests from the SPA, from the cookie set by the OAuth callback.
+pub(crate) fn access_token(headers: &HeaderMap) -> Option<&str> {
+    bearer_token(headers)
+        .or_else(|| cookie(headers, ACCESS_TOKEN_COOKIE).filter(|token| !token.is_empty()))
+}
+
+/// Returns the token of an `Authorization: Bearer` header. The scheme is case-insensitive.
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+/// Checks that the request carries `expected` in an `Authorization: Bearer` header. For tokens
+/// that are configured as secrets, such as the ones of `/metrics` and `/admin`.
+pub(crate) fn has_bearer_token(headers: &HeaderMap, expected: &SecretString) -> bool {
+    bearer_token(headers)
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), expected.expose().as_bytes()))
+}
+
+/// Compares without short-circuiting, so that response times do not reveal the token.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Returns the value of the cookie `name`, if the request carries it.
+pub(crate) fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
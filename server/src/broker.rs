@@ -0,0 +1,202 @@
+use ::serde::Serialize;
+use ::std::collections::VecDeque;
+use ::std::sync::{Arc, Mutex};
+use ::tokio::sync::broadcast;
+use ::tracing::{debug, warn};
+
+use crate::auth::User;
+
+/// How many events a slow subscriber may fall behind before it starts missing events.
+const CHANNEL_CAPACITY: usize = 256;
+
+/// How many of the most recent events are kept for clients that resume after a reconnect.
+const REPLAY_CAPACITY: usize = 256;
+
+/// An event published to a topic, as delivered to subscribers.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct Event {
+    /// Unique and increasing across all topics, so that clients can detect gaps and resume.
+    pub id: u64,
+    pub topic: String,
+    pub data: serde_json::Value,
+}
+
+/// Distributes events from handlers to connected clients.
+///
+/// All events go through one channel, in the order of their IDs. Subscribers filter for the
+/// topics they are interested in. Topic names are paths: `public/…` topics are open to every
+/// signed-in user, while `user/<GitHub user ID>/…` topics are reserved for the user with that ID.
+#[derive(Clone)]
+pub(crate) struct Broker {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    sender: broadcast::Sender<Event>,
+    /// The most recent events, oldest first.
+    replay: VecDeque<Event>,
+    last_event_id: u64,
+}
+
+/// A subscription that resumes after a given event.
+pub(crate) struct Resumed {
+    /// Buffered events after the given one, oldest first.
+    pub replayed: Vec<Event>,
+    /// `false` if events have been dropped from the replay buffer that the subscriber has not
+    /// seen, i.e. the subscriber needs to reload its state.
+    pub is_complete: bool,
+    /// Events after `replayed`.
+    pub receiver: broadcast::Receiver<Event>,
+}
+
+impl Default for Broker {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                sender: broadcast::channel(CHANNEL_CAPACITY).0,
+                replay: VecDeque::with_capacity(REPLAY_CAPACITY),
+                last_event_id: 0,
+            })),
+        }
+    }
+}
+
+impl Broker {
+    /// Sends `data` to all current subscribers of `topic`, and keeps it for replay.
+    pub(crate) fn publish(&self, topic: &str, data: impl Serialize) {
+        let data = match serde_json::to_value(data) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Cannot serialize event for topic {topic}: {e}");
+                return;
+            }
+        };
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_event_id += 1;
+        let event = Event {
+            id: inner.last_event_id,
+            topic: topic.to_string(),
+            data,
+        };
+        if inner.replay.len() == REPLAY_CAPACITY {
+            inner.replay.pop_front();
+        }
+        inner.replay.push_back(event.clone());
+        // Sending only fails if there are no subscribers, which is fine.
+        let _ = inner.sender.send(event);
+        debug!(topic, "Published event");
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.inner.lock().unwrap().sender.subscribe()
+    }
+
+    /// Subscribes, and replays buffered events with an ID greater than `last_event_id`. Together,
+    /// `replayed` and `receiver` yield every event exactly once. Without `last_event_id`, i.e.
+    /// for a new subscriber, there is nothing to replay: it only gets the events from now on.
+    pub(crate) fn resume_after(&self, last_event_id: Option<u64>) -> Resumed {
+        let inner = self.inner.lock().unwrap();
+        let Some(last_event_id) = last_event_id else {
+            return Resumed {
+                replayed: Vec::new(),
+                is_complete: true,
+                receiver: inner.sender.subscribe(),
+            };
+        };
+        // Event IDs start over when the server restarts. An ID from the future is from a
+        // previous run.
+        let is_from_this_run = last_event_id <= inner.last_event_id;
+        let last_event_id = if is_from_this_run { last_event_id } else { 0 };
+        let oldest_buffered_id = inner
+            .replay
+            .front()
+            .map_or(inner.last_event_id + 1, |event| event.id);
+        Resumed {
+            replayed: inner
+                .replay
+                .iter()
+                .filter(|event| event.id > last_event_id)
+                .cloned()
+                .collect(),
+            is_complete: is_from_this_run && last_event_id + 1 >= oldest_buffered_id,
+            receiver: inner.sender.subscribe(),
+        }
+    }
+}
+
+/// Returns the topic on which changes of a user's data are announced.
+pub(crate) fn user_topic(user_id: u64, name: &str) -> String {
+    format!("user/{user_id}/{name}")
+}
+
+/// Returns whether `user` may receive events of `topic`.
+pub(crate) fn may_subscribe(user: &User, topic: &str) -> bool {
+    match topic.split_once('/') {
+        Some(("public", rest)) => !rest.is_empty(),
+        Some(("user", rest)) => rest
+            .split_once('/')
+            .is_some_and(|(user_id, name)| user_id == user.id.to_string() && !name.is_empty()),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(events: &[Event]) -> Vec<u64> {
+        events.iter().map(|event| event.id).collect()
+    }
+
+    #[test]
+    fn new_subscribers_only_get_live_events() {
+        let broker = Broker::default();
+        for n in 0..(REPLAY_CAPACITY + 10) {
+            broker.publish("public/news", n);
+        }
+        let mut resumed = broker.resume_after(None);
+        assert!(resumed.replayed.is_empty());
+        assert!(resumed.is_complete);
+        broker.publish("public/news", "live");
+        let event = resumed.receiver.try_recv().unwrap();
+        assert_eq!(event.id, REPLAY_CAPACITY as u64 + 11);
+        assert!(resumed.receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn resuming_replays_the_missed_events() {
+        let broker = Broker::default();
+        for n in 0..5 {
+            broker.publish("public/news", n);
+        }
+        let mut resumed = broker.resume_after(Some(3));
+        assert_eq!(ids(&resumed.replayed), [4, 5]);
+        assert!(resumed.is_complete);
+        broker.publish("public/news", "live");
+        assert_eq!(resumed.receiver.try_recv().unwrap().id, 6);
+    }
+
+    #[test]
+    fn resuming_after_dropped_events_requires_a_resync() {
+        let broker = Broker::default();
+        for n in 0..(REPLAY_CAPACITY + 10) {
+            broker.publish("public/news", n);
+        }
+        let resumed = broker.resume_after(Some(5));
+        assert!(!resumed.is_complete);
+        assert_eq!(resumed.replayed.len(), REPLAY_CAPACITY);
+        // The last buffered event has been seen, nothing is missing.
+        let resumed = broker.resume_after(Some(REPLAY_CAPACITY as u64 + 10));
+        assert!(resumed.is_complete);
+        assert!(resumed.replayed.is_empty());
+    }
+
+    #[test]
+    fn ids_from_a_previous_run_replay_everything() {
+        let broker = Broker::default();
+        broker.publish("public/news", 1);
+        let resumed = broker.resume_after(Some(1000));
+        assert!(!resumed.is_complete);
+        assert_eq!(ids(&resumed.replayed), [1]);
+    }
+}
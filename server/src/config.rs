@@ -0,0 +1,246 @@
+//! The configuration of the server, merged from several sources. From lowest to highest
+//! precedence:
+//!
+//! 1. the defaults in this file
+//! 2. the TOML file in `CONFIG_FILE` (`server/Config.toml` by default, if it exists)
+//! 3. the Shuttle secret store (`server/Secrets.toml`), or for the standalone server the TOML file
+//!    in `SECRETS_FILE` (`server/Secrets.toml` by default, if it exists)
+//! 4. environment variables of the same name
+//!
+//! Every key is validated before the server starts, and all missing or invalid keys are reported
+//! at once.
+//!
+//! The configuration can be reloaded while the server runs, on `SIGHUP` or with
+//! `POST /admin/config/reload` (see [`SharedConfig`]). The Shuttle secret store and the
+//! environment are fixed for the lifetime of the process, so changes must be made in the files.
+//! Most keys take effect immediately, those in [`RESTART_KEYS`] after a restart.
+
+use ::anyhow::Result;
+use ::axum::http::HeaderName;
+use ::std::num::NonZeroUsize;
+use ::std::time::Duration;
+use ::tracing::debug;
+
+use self::layers::Layers;
+use self::value::Parser;
+use crate::cors::AllowedOrigins;
+use crate::http_client::UpstreamUrls;
+use crate::limits::LimitsConfig;
+use crate::redact::SecretString;
+use crate::route::spa::{self, CacheRules};
+use crate::security::SecurityConfig;
+
+pub(crate) use self::layers::Layer;
+pub(crate) use self::shared::{reload_on_sighup, SharedConfig};
+pub(crate) use self::value::{ConfigValue, Settings};
+
+mod layers;
+mod shared;
+mod value;
+
+const KEY_GITHUB_APP_CLIENT_SECRET: &str = "GITHUB_APP_CLIENT_SECRET";
+const KEY_TRUSTED_PROXY_HEADER: &str = "TRUSTED_PROXY_HEADER";
+const KEY_METRICS_BEARER_TOKEN: &str = "METRICS_BEARER_TOKEN";
+const KEY_ADMIN_BEARER_TOKEN: &str = "ADMIN_BEARER_TOKEN";
+const KEY_GITHUB_APP_CLIENT_ID: &str = "GITHUB_APP_CLIENT_ID";
+const KEY_ENVIRONMENT: &str = "ENVIRONMENT";
+const KEY_API_BASE_URL: &str = "API_BASE_URL";
+const KEY_FEATURE_FLAGS: &str = "FEATURE_FLAGS";
+const KEY_CSP_REPORT_ONLY: &str = "CSP_REPORT_ONLY";
+const KEY_HSTS_MAX_AGE_SECONDS: &str = "HSTS_MAX_AGE_SECONDS";
+const KEY_GITHUB_APP_CLIENT_SECRET_GRACE_PERIOD: &str = "GITHUB_APP_CLIENT_SECRET_GRACE_PERIOD";
+const KEY_REQUEST_TIMEOUT: &str = "REQUEST_TIMEOUT";
+const KEY_OAUTH_REQUEST_TIMEOUT: &str = "OAUTH_REQUEST_TIMEOUT";
+const KEY_MAX_REQUEST_BODY_BYTES: &str = "MAX_REQUEST_BODY_BYTES";
+const KEY_MAX_CONCURRENT_REQUESTS: &str = "MAX_CONCURRENT_REQUESTS";
+const KEY_OUTBOUND_CONNECT_TIMEOUT: &str = "OUTBOUND_CONNECT_TIMEOUT";
+const KEY_OUTBOUND_REQUEST_TIMEOUT: &str = "OUTBOUND_REQUEST_TIMEOUT";
+const KEY_GITHUB_BASE_URL: &str = "GITHUB_BASE_URL";
+const KEY_GITHUB_API_BASE_URL: &str = "GITHUB_API_BASE_URL";
+const KEY_CORS_ALLOWED_ORIGINS: &str = "CORS_ALLOWED_ORIGINS";
+const KEY_CORS_MAX_AGE: &str = "CORS_MAX_AGE";
+const KEY_SPA_CACHE_RULES: &str = "SPA_CACHE_RULES";
+
+/// All keys. Others in a file are reported as typos.
+const KEYS: &[&str] = &[
+    KEY_GITHUB_APP_CLIENT_SECRET,
+    KEY_GITHUB_APP_CLIENT_SECRET_GRACE_PERIOD,
+    KEY_GITHUB_APP_CLIENT_ID,
+    KEY_ENVIRONMENT,
+    KEY_API_BASE_URL,
+    KEY_FEATURE_FLAGS,
+    KEY_TRUSTED_PROXY_HEADER,
+    KEY_METRICS_BEARER_TOKEN,
+    KEY_ADMIN_BEARER_TOKEN,
+    KEY_CSP_REPORT_ONLY,
+    KEY_HSTS_MAX_AGE_SECONDS,
+    KEY_REQUEST_TIMEOUT,
+    KEY_OAUTH_REQUEST_TIMEOUT,
+    KEY_MAX_REQUEST_BODY_BYTES,
+    KEY_MAX_CONCURRENT_REQUESTS,
+    KEY_OUTBOUND_CONNECT_TIMEOUT,
+    KEY_OUTBOUND_REQUEST_TIMEOUT,
+    KEY_GITHUB_BASE_URL,
+    KEY_GITHUB_API_BASE_URL,
+    KEY_CORS_ALLOWED_ORIGINS,
+    KEY_CORS_MAX_AGE,
+    KEY_SPA_CACHE_RULES,
+];
+
+/// The keys that are only read at startup, by the middleware of the router and the HTTP client
+const RESTART_KEYS: &[&str] = &[
+    KEY_TRUSTED_PROXY_HEADER,
+    KEY_CSP_REPORT_ONLY,
+    KEY_HSTS_MAX_AGE_SECONDS,
+    KEY_REQUEST_TIMEOUT,
+    KEY_OAUTH_REQUEST_TIMEOUT,
+    KEY_MAX_REQUEST_BODY_BYTES,
+    KEY_MAX_CONCURRENT_REQUESTS,
+    KEY_OUTBOUND_CONNECT_TIMEOUT,
+    KEY_OUTBOUND_REQUEST_TIMEOUT,
+    KEY_GITHUB_BASE_URL,
+    KEY_GITHUB_API_BASE_URL,
+    KEY_CORS_MAX_AGE,
+];
+
+const DEFAULTS: &[(&str, &str)] = &[
+    (KEY_GITHUB_APP_CLIENT_SECRET_GRACE_PERIOD, "1h"),
+    // The GitHub App of the production deployment
+    (KEY_GITHUB_APP_CLIENT_ID, "Iv1.b5ba4dcd32da9063"),
+    (KEY_ENVIRONMENT, "development"),
+    (KEY_API_BASE_URL, "/api"),
+    (KEY_CSP_REPORT_ONLY, "false"),
+    // Two years, as recommended for the HSTS preload list
+    (KEY_HSTS_MAX_AGE_SECONDS, "63072000"),
+    (KEY_REQUEST_TIMEOUT, "10s"),
+    // Enough for two token requests, see `route::oauth`
+    (KEY_OAUTH_REQUEST_TIMEOUT, "30s"),
+    (KEY_MAX_REQUEST_BODY_BYTES, "65536"),
+    (KEY_MAX_CONCURRENT_REQUESTS, "512"),
+    (KEY_OUTBOUND_CONNECT_TIMEOUT, "5s"),
+    (KEY_OUTBOUND_REQUEST_TIMEOUT, "10s"),
+    // Overridden to point GitHub traffic at a fake server, see `crate::http_client`
+    (KEY_GITHUB_BASE_URL, "https://github.com"),
+    (KEY_GITHUB_API_BASE_URL, "https://api.github.com"),
+    // Chromium caps it at 2 hours.
+    (KEY_CORS_MAX_AGE, "1h"),
+    (KEY_SPA_CACHE_RULES, spa::DEFAULT_CACHE_RULES),
+];
+
+pub(crate) struct Config {
+    pub github_app_client_secret: SecretString,
+    /// How long the previous client secret remains valid after a reload changed it
+    pub github_app_client_secret_grace_period: Duration,
+    /// The header in which a reverse proxy in front of the server passes the client IP address
+    /// (e.g. `X-Forwarded-For`). Unset if there is no such proxy.
+    pub trusted_proxy_header: Option<HeaderName>,
+    /// If set, `/metrics` requires this token in an `Authorization: Bearer` header.
+    pub metrics_bearer_token: Option<SecretString>,
+    /// The token that `/admin` requires in an `Authorization: Bearer` header. Without it,
+    /// `/admin` is disabled.
+    pub admin_bearer_token: Option<SecretString>,
+    /// The client ID of the GitHub App. Unlike the client secret, it is public.
+    pub github_app_client_id: String,
+    /// The name of the deployment, e.g. `staging` or `production`. The UI gets it, too.
+    pub environment: String,
+    /// Where the UI finds the API.
+    pub api_base_url: String,
+    /// The names of the features that are enabled in the UI.
+    pub feature_flags: Vec<String>,
+    pub security: SecurityConfig,
+    pub limits: LimitsConfig,
+    pub upstream_urls: UpstreamUrls,
+    /// The other origins that may call `/api`
+    pub cors_allowed_origins: AllowedOrigins,
+    /// How long browsers may cache the answer to a CORS preflight request
+    pub cors_max_age: Duration,
+    /// How long browsers may cache the files of the UI
+    pub spa_cache_rules: CacheRules,
+}
+
+/// Reads and validates every key. `secrets` is the Shuttle secret store, or the secrets file of
+/// the standalone server. `Settings` tells where each value came from.
+pub(crate) fn load(secrets: Option<Layer>) -> Result<(Config, Settings)> {
+    let layers = Layers::new([
+        Some(Layer::defaults()),
+        Layer::file_from_env("CONFIG_FILE", "server/Config.toml")?,
+        secrets,
+        Some(Layer::environment()),
+    ]);
+    let mut parser = Parser::new(&layers);
+    let github_app_client_secret = parser.required(KEY_GITHUB_APP_CLIENT_SECRET);
+    let github_app_client_secret_grace_period =
+        parser.required(KEY_GITHUB_APP_CLIENT_SECRET_GRACE_PERIOD);
+    let github_app_client_id = parser.required(KEY_GITHUB_APP_CLIENT_ID);
+    let environment = parser.required(KEY_ENVIRONMENT);
+    let api_base_url = parser.required::<ApiBaseUrl>(KEY_API_BASE_URL);
+    let feature_flags = parser.optional(KEY_FEATURE_FLAGS);
+    let trusted_proxy_header = parser.optional(KEY_TRUSTED_PROXY_HEADER);
+    let metrics_bearer_token = parser.optional(KEY_METRICS_BEARER_TOKEN);
+    let admin_bearer_token = parser.optional(KEY_ADMIN_BEARER_TOKEN);
+    let csp_report_only = parser.required(KEY_CSP_REPORT_ONLY);
+    let hsts_max_age = parser.required::<Duration>(KEY_HSTS_MAX_AGE_SECONDS);
+    let request_timeout = parser.required(KEY_REQUEST_TIMEOUT);
+    let oauth_request_timeout = parser.required(KEY_OAUTH_REQUEST_TIMEOUT);
+    let max_request_body_bytes = parser.required::<NonZeroUsize>(KEY_MAX_REQUEST_BODY_BYTES);
+    let max_concurrent_requests = parser.required::<NonZeroUsize>(KEY_MAX_CONCURRENT_REQUESTS);
+    let outbound_connect_timeout = parser.required(KEY_OUTBOUND_CONNECT_TIMEOUT);
+    let outbound_request_timeout = parser.required(KEY_OUTBOUND_REQUEST_TIMEOUT);
+    let github_base_url = parser.required(KEY_GITHUB_BASE_URL);
+    let github_api_base_url = parser.required(KEY_GITHUB_API_BASE_URL);
+    let cors_allowed_origins = parser.optional(KEY_CORS_ALLOWED_ORIGINS);
+    let cors_max_age = parser.required(KEY_CORS_MAX_AGE);
+    let spa_cache_rules = parser.required(KEY_SPA_CACHE_RULES);
+
+    let config = (|| {
+        Some(Config {
+            github_app_client_secret: github_app_client_secret?,
+            github_app_client_secret_grace_period: github_app_client_secret_grace_period?,
+            trusted_proxy_header,
+            metrics_bearer_token,
+            admin_bearer_token,
+            github_app_client_id: github_app_client_id?,
+            environment: environment?,
+            api_base_url: api_base_url?.0,
+            feature_flags: feature_flags.unwrap_or_default(),
+            security: SecurityConfig {
+                csp_report_only: csp_report_only?,
+                // `0` disables `Strict-Transport-Security`.
+                hsts_max_age: Some(hsts_max_age?).filter(|max_age| !max_age.is_zero()),
+            },
+            limits: LimitsConfig {
+                request_timeout: request_timeout?,
+                oauth_request_timeout: oauth_request_timeout?,
+                max_request_body_bytes: max_request_body_bytes?.get(),
+                max_concurrent_requests: max_concurrent_requests?.get(),
+                outbound_connect_timeout: outbound_connect_timeout?,
+                outbound_request_timeout: outbound_request_timeout?,
+            },
+            upstream_urls: UpstreamUrls {
+                github: github_base_url?,
+                github_api: github_api_base_url?,
+            },
+            cors_allowed_origins: cors_allowed_origins.unwrap_or_default(),
+            cors_max_age: cors_max_age?,
+            spa_cache_rules: spa_cache_rules?,
+        })
+    })();
+    let (config, settings) = parser.finish(config)?;
+    debug!("Configuration:\n{settings}");
+    Ok((config, settings))
+}
+
+/// A path on the same origin, such as `/api`, or an absolute URL
+struct ApiBaseUrl(String);
+
+impl ConfigValue for ApiBaseUrl {
+    fn parse(value: &str) -> Result<Self, String> {
+        if value.starts_with('/') {
+            Ok(ApiBaseUrl(value.to_string()))
+        } else {
+            <::reqwest::Url as ConfigValue>::parse(value)
+                .map(|_| ApiBaseUrl(value.to_string()))
+                .map_err(|_| "must be a path such as /api, or an absolute http or https URL".into())
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use ::anyhow::{anyhow, Context, Result};
+#[cfg(not(feature = "standalone"))]
+use ::shuttle_runtime::SecretStore as ShuttleSecretStore;
+use ::std::collections::BTreeMap;
+use ::std::fmt;
+use ::std::path::PathBuf;
+use ::tracing::warn;
+
+use super::{DEFAULTS, KEYS};
+
+/// Where a value comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Source {
+    Default,
+    File(PathBuf),
+    #[cfg_attr(feature = "standalone", allow(dead_code))]
+    SecretStore,
+    Environment,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => f.write_str("the defaults"),
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::SecretStore => f.write_str("the Shuttle secret store"),
+            Source::Environment => f.write_str("the environment"),
+        }
+    }
+}
+
+/// The values of one source.
+#[derive(Clone)]
+pub(crate) struct Layer {
+    source: Source,
+    values: BTreeMap<String, String>,
+}
+
+impl Layer {
+    pub(super) fn defaults() -> Self {
+        Layer {
+            source: Source::Default,
+            values: DEFAULTS
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    /// Reads the TOML file named by the environment variable `path_variable`, or else
+    /// `default_path` if it exists.
+    pub(crate) fn file_from_env(path_variable: &str, default_path: &str) -> Result<Option<Self>> {
+        match std::env::var_os(path_variable) {
+            Some(path) => Layer::file(PathBuf::from(path)).map(Some),
+            None => Some(PathBuf::from(default_path))
+                .filter(|path| path.is_file())
+                .map(Layer::file)
+                .transpose(),
+        }
+    }
+
+    /// Shuttle's format: a flat table. Strings are expected, but numbers and booleans are
+    /// accepted as well.
+    fn file(path: PathBuf) -> Result<Self> {
+        let table: ::toml::Table = std::fs::read_to_string(&path)
+            .with_context(|| format!("Cannot read {}", path.display()))?
+            .parse()
+            .with_context(|| format!("{} is not valid TOML", path.display()))?;
+        let values = table
+            .into_iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    ::toml::Value::String(value) => value,
+                    ::toml::Value::Integer(value) => value.to_string(),
+                    ::toml::Value::Float(value) => value.to_string(),
+                    ::toml::Value::Boolean(value) => value.to_string(),
+                    _ => return Err(anyhow!("{key} in {} must be a string", path.display())),
+                };
+                Ok((key, value))
+            })
+            .collect::<Result<BTreeMap<_, _>>>()?;
+        for key in values.keys().filter(|key| !KEYS.contains(&key.as_str())) {
+            // Probably a typo, which would otherwise go unnoticed
+            warn!("Unknown key {key} in {}", path.display());
+        }
+        Ok(Layer {
+            source: Source::File(path),
+            values,
+        })
+    }
+
+    /// The store may hold other secrets, so only known keys are read.
+    #[cfg(not(feature = "standalone"))]
+    pub(crate) fn secret_store(store: &ShuttleSecretStore) -> Self {
+        Layer {
+            source: Source::SecretStore,
+            values: KEYS
+                .iter()
+                .filter_map(|key| Some((key.to_string(), store.get(key)?)))
+                .collect(),
+        }
+    }
+
+    /// Stands in for the secret store in tests.
+    #[cfg(test)]
+    pub(super) fn for_tests(values: &[(&str, &str)]) -> Self {
+        Layer {
+            source: Source::SecretStore,
+            values: values
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    pub(super) fn environment() -> Self {
+        Layer::environment_from(|key| std::env::var(key).ok())
+    }
+
+    /// The environment layer, with the variables that `var` looks up. Only known keys are read.
+    fn environment_from(var: impl Fn(&str) -> Option<String>) -> Self {
+        Layer {
+            source: Source::Environment,
+            values: KEYS
+                .iter()
+                .filter_map(|key| Some((key.to_string(), var(key)?)))
+                .collect(),
+        }
+    }
+}
+
+/// All sources, from the lowest to the highest precedence.
+pub(super) struct Layers(Vec<Layer>);
+
+impl Layers {
+    pub(super) fn new(layers: impl IntoIterator<Item = Option<Layer>>) -> Self {
+        Layers(layers.into_iter().flatten().collect())
+    }
+
+    pub(super) fn get(&self, key: &str) -> Option<(&str, &Source)> {
+        self.0
+            .iter()
+            .rev()
+            .find_map(|layer| Some((layer.values.get(key)?.as_str(), &layer.source)))
+    }
+
+    /// Lists the sources that could provide a missing value.
+    pub(super) fn describe(&self) -> String {
+        self.0
+            .iter()
+            .filter(|layer| !matches!(layer.source, Source::Default))
+            .map(|layer| layer.source.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// A `Config.toml` with `contents`, in a file of its own
+    pub(in crate::config) fn file(name: &str, contents: &str) -> Layer {
+        let path = std::env::temp_dir().join(format!("config-{name}-{}.toml", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        Layer::file(path).unwrap()
+    }
+
+    /// An environment with the variables in `values`, and no others
+    pub(in crate::config) fn environment(values: &[(&str, &str)]) -> Layer {
+        let values: BTreeMap<_, _> = values
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Layer::environment_from(|key| values.get(key).cloned())
+    }
+
+    #[test]
+    fn later_layers_take_precedence() {
+        let file = file(
+            "precedence",
+            "ENVIRONMENT = 'staging'\nREQUEST_TIMEOUT = 20\nCSP_REPORT_ONLY = true",
+        );
+        let file_source = file.source.clone();
+        let layers = Layers::new([
+            Some(Layer::defaults()),
+            Some(file),
+            Some(Layer::for_tests(&[
+                ("ENVIRONMENT", "production"),
+                ("API_BASE_URL", "https://api.example.com"),
+            ])),
+            Some(environment(&[
+                ("API_BASE_URL", "http://localhost:8000/api"),
+                ("UNKNOWN_KEY", "ignored"),
+            ])),
+        ]);
+
+        assert_eq!(
+            layers.get("API_BASE_URL"),
+            Some(("http://localhost:8000/api", &Source::Environment))
+        );
+        assert_eq!(
+            layers.get("ENVIRONMENT"),
+            Some(("production", &Source::SecretStore))
+        );
+        assert_eq!(layers.get("REQUEST_TIMEOUT"), Some(("20", &file_source)));
+        assert_eq!(layers.get("CSP_REPORT_ONLY"), Some(("true", &file_source)));
+        assert_eq!(
+            layers.get("OAUTH_REQUEST_TIMEOUT"),
+            Some(("30s", &Source::Default))
+        );
+        assert_eq!(layers.get("TRUSTED_PROXY_HEADER"), None);
+        assert_eq!(layers.get("UNKNOWN_KEY"), None);
+        assert_eq!(
+            layers.describe(),
+            format!("{file_source}, the Shuttle secret store, the environment")
+        );
+    }
+
+    #[test]
+    fn files_must_be_flat_tables_of_scalars() {
+        let path = std::env::temp_dir().join(format!("config-nested-{}.toml", std::process::id()));
+        std::fs::write(&path, "[github]\nclient_id = 'abc'").unwrap();
+        let error = Layer::file(path).err().unwrap();
+        assert!(error.to_string().contains("github in "), "{error}");
+    }
+}
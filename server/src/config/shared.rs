@@ -0,0 +1,115 @@
+use ::anyhow::Result;
+use ::arc_swap::ArcSwap;
+use ::std::sync::{Arc, Mutex};
+use ::std::time::Instant;
+use ::tracing::{info, warn};
+
+use super::{load, Config, Layer, Settings, RESTART_KEYS};
+use crate::redact::SecretString;
+
+/// The configuration of the running server. [`SharedConfig::reload`] swaps it atomically:
+/// requests in flight keep the version they started with, later ones get the new one. Read it
+/// with [`SharedConfig::current`] for every request, rather than keeping a copy.
+#[derive(Clone)]
+pub(crate) struct SharedConfig(Arc<Shared>);
+
+struct Shared {
+    current: ArcSwap<Config>,
+    /// Reads the secret store again, see [`load`]
+    secrets: Box<dyn Fn() -> Result<Option<Layer>> + Send + Sync>,
+    /// Of the current configuration. The lock also keeps reloads from overlapping.
+    settings: Mutex<Settings>,
+    /// The client secret before the last change, and the end of its grace period
+    previous_client_secret: Mutex<Option<(SecretString, Instant)>>,
+}
+
+impl SharedConfig {
+    pub(crate) fn new(
+        config: Config,
+        settings: Settings,
+        secrets: impl Fn() -> Result<Option<Layer>> + Send + Sync + 'static,
+    ) -> Self {
+        SharedConfig(Arc::new(Shared {
+            current: ArcSwap::from_pointee(config),
+            secrets: Box::new(secrets),
+            settings: Mutex::new(settings),
+            previous_client_secret: Mutex::new(None),
+        }))
+    }
+
+    /// Loads a configuration with `secrets` as the secret store, for tests.
+    #[cfg(test)]
+    pub(crate) fn for_tests(secrets: &[(&str, &str)]) -> Self {
+        let secrets = Layer::for_tests(secrets);
+        let (config, settings) = load(Some(secrets.clone())).unwrap();
+        SharedConfig::new(config, settings, move || Ok(Some(secrets.clone())))
+    }
+
+    pub(crate) fn current(&self) -> Arc<Config> {
+        self.0.current.load_full()
+    }
+
+    /// The current client secret, followed by the previous one during its grace period. This lets
+    /// sign-ins succeed while a rotated secret is being rolled out.
+    pub(crate) fn github_app_client_secrets(&self) -> Vec<SecretString> {
+        let mut secrets = vec![self.current().github_app_client_secret.clone()];
+        let mut previous = self.0.previous_client_secret.lock().unwrap();
+        match previous.as_ref() {
+            Some((secret, until)) if Instant::now() < *until => secrets.push(secret.clone()),
+            Some(_) => *previous = None,
+            None => (),
+        }
+        secrets
+    }
+
+    /// Loads the configuration from its sources again. If it is invalid, the current one stays
+    /// in place.
+    pub(crate) fn reload(&self) -> Result<Settings> {
+        let mut settings = self.0.settings.lock().unwrap();
+        let (config, new_settings) = load((self.0.secrets)()?)?;
+        let current = self.current();
+        if config.github_app_client_secret != current.github_app_client_secret {
+            let grace_period = config.github_app_client_secret_grace_period;
+            *self.0.previous_client_secret.lock().unwrap() = Some((
+                current.github_app_client_secret.clone(),
+                Instant::now() + grace_period,
+            ));
+            info!(
+                "GITHUB_APP_CLIENT_SECRET has changed. The previous one remains valid for {}s",
+                grace_period.as_secs()
+            );
+        }
+        for key in settings.changed_keys(&new_settings) {
+            if RESTART_KEYS.contains(&key) {
+                warn!("{key} has changed, which only takes effect after a restart");
+            }
+        }
+        self.0.current.store(Arc::new(config));
+        *settings = new_settings.clone();
+        info!("Reloaded the configuration");
+        Ok(new_settings)
+    }
+}
+
+/// Reloads the configuration on `SIGHUP`, like many daemons do.
+pub(crate) fn reload_on_sighup(config: SharedConfig) {
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use ::tokio::signal::unix::{signal, SignalKind};
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(e) => {
+                warn!("Cannot listen for SIGHUP, so only /admin can reload the configuration: {e}");
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            info!("Received SIGHUP, reloading the configuration");
+            if let Err(e) = config.reload() {
+                ::tracing::error!("Keeping the current configuration. {e:#}");
+            }
+        }
+    });
+    #[cfg(not(unix))]
+    let _ = config;
+}
@@ -0,0 +1,327 @@
+use ::anyhow::{anyhow, Result};
+use ::axum::http::HeaderName;
+use ::std::fmt;
+use ::std::num::NonZeroUsize;
+use ::std::time::Duration;
+
+use super::layers::{Layers, Source};
+use crate::redact::{Redacted, SecretString};
+
+/// A type that a configuration value can be parsed into. Every source provides strings.
+pub(crate) trait ConfigValue: Sized {
+    /// On error, returns what is expected, e.g. "must be true or false".
+    fn parse(value: &str) -> Result<Self, String>;
+
+    /// Secrets are masked in errors and in `--print-config`.
+    const IS_SECRET: bool = false;
+}
+
+impl ConfigValue for String {
+    fn parse(value: &str) -> Result<Self, String> {
+        if value.trim().is_empty() {
+            Err("must not be empty".to_string())
+        } else {
+            Ok(value.to_string())
+        }
+    }
+}
+
+impl ConfigValue for SecretString {
+    fn parse(value: &str) -> Result<Self, String> {
+        String::parse(value).map(Redacted::new)
+    }
+
+    const IS_SECRET: bool = true;
+}
+
+impl ConfigValue for bool {
+    fn parse(value: &str) -> Result<Self, String> {
+        value
+            .parse()
+            .map_err(|_| "must be true or false".to_string())
+    }
+}
+
+impl ConfigValue for NonZeroUsize {
+    fn parse(value: &str) -> Result<Self, String> {
+        value
+            .trim()
+            .parse()
+            .map_err(|_| "must be a positive whole number".to_string())
+    }
+}
+
+/// A number of seconds, or a number with one of the units `s`, `m`, `h` and `d`, such as `30m`
+impl ConfigValue for Duration {
+    fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let (number, unit_seconds) = match value.char_indices().last() {
+            Some((index, 's')) => (&value[..index], 1),
+            Some((index, 'm')) => (&value[..index], 60),
+            Some((index, 'h')) => (&value[..index], 60 * 60),
+            Some((index, 'd')) => (&value[..index], 24 * 60 * 60),
+            _ => (value, 1),
+        };
+        number
+            .parse::<u64>()
+            .ok()
+            .and_then(|number| number.checked_mul(unit_seconds))
+            .map(Duration::from_secs)
+            .ok_or_else(|| "must be a number of seconds, or e.g. 30m, 12h or 7d".to_string())
+    }
+}
+
+impl ConfigValue for HeaderName {
+    fn parse(value: &str) -> Result<Self, String> {
+        HeaderName::try_from(value).map_err(|_| "must be an HTTP header name".to_string())
+    }
+}
+
+/// An absolute `http` or `https` URL
+impl ConfigValue for ::reqwest::Url {
+    fn parse(value: &str) -> Result<Self, String> {
+        ::reqwest::Url::parse(value)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .ok_or_else(|| "must be an absolute http or https URL".to_string())
+    }
+}
+
+/// A comma-separated list. Blank items are skipped.
+impl ConfigValue for Vec<String> {
+    fn parse(value: &str) -> Result<Self, String> {
+        Ok(value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+}
+
+/// Reads keys from the layers, and collects all errors instead of stopping at the first.
+pub(super) struct Parser<'a> {
+    layers: &'a Layers,
+    errors: Vec<String>,
+    settings: Vec<Setting>,
+}
+
+impl<'a> Parser<'a> {
+    pub(super) fn new(layers: &'a Layers) -> Self {
+        Parser {
+            layers,
+            errors: Vec::new(),
+            settings: Vec::new(),
+        }
+    }
+
+    pub(super) fn required<T: ConfigValue>(&mut self, key: &'static str) -> Option<T> {
+        let value = self.optional(key);
+        if self.layers.get(key).is_none() {
+            self.errors.push(format!(
+                "{key} is missing. Set it in one of: {}",
+                self.layers.describe()
+            ));
+        }
+        value
+    }
+
+    pub(super) fn optional<T: ConfigValue>(&mut self, key: &'static str) -> Option<T> {
+        let Some((value, source)) = self.layers.get(key) else {
+            self.settings.push(Setting { key, value: None });
+            return None;
+        };
+        let shown = if T::IS_SECRET {
+            format!("\"{}\"", Redacted::new(value))
+        } else {
+            format!("{value:?}")
+        };
+        self.settings.push(Setting {
+            key,
+            value: Some((shown.clone(), source.clone())),
+        });
+        T::parse(value)
+            .map_err(|e| {
+                self.errors
+                    .push(format!("{key} = {shown} from {source}: {e}"))
+            })
+            .ok()
+    }
+
+    /// Returns `config` unless there were errors. `config` is `None` only if there were.
+    pub(super) fn finish<T>(self, config: Option<T>) -> Result<(T, Settings)> {
+        match config {
+            Some(config) if self.errors.is_empty() => Ok((config, Settings(self.settings))),
+            _ => Err(anyhow!(
+                "Invalid configuration:\n  {}",
+                self.errors.join("\n  ")
+            )),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+struct Setting {
+    key: &'static str,
+    /// Shown as it would be written in a TOML file, or masked
+    value: Option<(String, Source)>,
+}
+
+/// The effective value and the source of every key, with secrets masked
+#[derive(Clone)]
+pub(crate) struct Settings(Vec<Setting>);
+
+impl Settings {
+    /// The keys whose value or source differs in `other`. Masked secrets always look the same.
+    pub(super) fn changed_keys(&self, other: &Settings) -> Vec<&'static str> {
+        other
+            .0
+            .iter()
+            .filter(|setting| !self.0.contains(setting))
+            .map(|setting| setting.key)
+            .collect()
+    }
+}
+
+/// In TOML syntax, with each source in a comment
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for Setting { key, value } in &self.0 {
+            match value {
+                Some((value, source)) => writeln!(f, "{key} = {value} # from {source}")?,
+                None => writeln!(f, "# {key} is not set")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::reqwest::Url;
+
+    use super::super::layers::{self, Layer};
+    use super::*;
+
+    #[test]
+    fn durations_are_seconds_or_have_a_unit() {
+        for (value, seconds) in [
+            ("90", 90),
+            (" 45s ", 45),
+            ("30m", 30 * 60),
+            ("12h", 12 * 60 * 60),
+            ("7d", 7 * 24 * 60 * 60),
+            ("0", 0),
+        ] {
+            assert_eq!(
+                Duration::parse(value),
+                Ok(Duration::from_secs(seconds)),
+                "{value}"
+            );
+        }
+        for value in ["", "s", "1.5h", "-1", "10 m", "1w", "99999999999999999999d"] {
+            assert!(Duration::parse(value).is_err(), "{value} is accepted");
+        }
+    }
+
+    #[test]
+    fn urls_must_be_absolute_http_urls() {
+        let url = <Url as ConfigValue>::parse("https://api.github.com").unwrap();
+        assert_eq!(url.as_str(), "https://api.github.com/");
+        assert!(<Url as ConfigValue>::parse("http://localhost:8080/github").is_ok());
+        for value in ["/api", "api.github.com", "ftp://example.com", "file:///etc"] {
+            assert!(
+                <Url as ConfigValue>::parse(value).is_err(),
+                "{value} is accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn other_values() {
+        assert_eq!(bool::parse("true"), Ok(true));
+        assert!(bool::parse("yes").is_err());
+        assert_eq!(NonZeroUsize::parse(" 512 ").unwrap().get(), 512);
+        assert!(NonZeroUsize::parse("0").is_err());
+        assert!(String::parse("  ").is_err());
+        assert_eq!(
+            Vec::<String>::parse(" a, ,b ,").unwrap(),
+            vec!["a".to_string(), "b".to_string()]
+        );
+    }
+
+    #[test]
+    fn all_errors_are_reported_at_once() {
+        let layers = Layers::new([
+            Some(Layer::defaults()),
+            Some(layers::tests::file(
+                "errors",
+                "REQUEST_TIMEOUT = 'soon'\nGITHUB_API_BASE_URL = 'api.github.com'",
+            )),
+            Some(layers::tests::environment(&[("ADMIN_BEARER_TOKEN", " ")])),
+        ]);
+        let mut parser = Parser::new(&layers);
+        let secret = parser.required::<SecretString>("GITHUB_APP_CLIENT_SECRET");
+        let timeout = parser.required::<Duration>("REQUEST_TIMEOUT");
+        let url = parser.required::<Url>("GITHUB_API_BASE_URL");
+        let admin_token = parser.optional::<SecretString>("ADMIN_BEARER_TOKEN");
+        let metrics_token = parser.optional::<SecretString>("METRICS_BEARER_TOKEN");
+        let environment = parser.required::<String>("ENVIRONMENT");
+        assert!(secret.is_none() && timeout.is_none() && url.is_none() && admin_token.is_none());
+        assert!(metrics_token.is_none());
+        assert_eq!(environment.as_deref(), Some("development"));
+
+        let error = parser.finish(environment).err().unwrap().to_string();
+        let lines: Vec<_> = error.lines().map(str::trim).collect();
+        let file = std::env::temp_dir().join(format!("config-errors-{}.toml", std::process::id()));
+        assert_eq!(
+            lines,
+            [
+                "Invalid configuration:".to_string(),
+                format!(
+                    "GITHUB_APP_CLIENT_SECRET is missing. Set it in one of: {}, the environment",
+                    file.display()
+                ),
+                format!(
+                    "REQUEST_TIMEOUT = \"soon\" from {}: must be a number of seconds, or e.g. \
+                     30m, 12h or 7d",
+                    file.display()
+                ),
+                format!(
+                    "GITHUB_API_BASE_URL = \"api.github.com\" from {}: must be an absolute http \
+                     or https URL",
+                    file.display()
+                ),
+                // The value of a secret is masked.
+                "ADMIN_BEARER_TOKEN = \"[redacted]\" from the environment: must not be empty"
+                    .to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn settings_show_values_and_sources() {
+        let layers = Layers::new([
+            Some(Layer::defaults()),
+            Some(Layer::for_tests(&[("GITHUB_APP_CLIENT_SECRET", "s3cr3t")])),
+            Some(layers::tests::environment(&[("REQUEST_TIMEOUT", "5s")])),
+        ]);
+        let mut parser = Parser::new(&layers);
+        let secret = parser.required::<SecretString>("GITHUB_APP_CLIENT_SECRET");
+        let timeout = parser.required::<Duration>("REQUEST_TIMEOUT");
+        let proxy_header = parser.optional::<HeaderName>("TRUSTED_PROXY_HEADER");
+        let environment = parser.required::<String>("ENVIRONMENT");
+        assert_eq!(secret.unwrap().expose(), "s3cr3t");
+        assert_eq!(timeout, Some(Duration::from_secs(5)));
+        assert!(proxy_header.is_none());
+
+        let (_, settings) = parser.finish(environment).unwrap();
+        assert_eq!(
+            settings.to_string(),
+            "GITHUB_APP_CLIENT_SECRET = \"[redacted]\" # from the Shuttle secret store\n\
+             REQUEST_TIMEOUT = \"5s\" # from the environment\n\
+             # TRUSTED_PROXY_HEADER is not set\n\
+             ENVIRONMENT = \"development\" # from the defaults\n"
+        );
+    }
+}
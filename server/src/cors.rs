@@ -0,0 +1,269 @@
+//! Cross-origin access to `/api`, for front-ends other than the bundled SPA, such as a mobile web
+//! shell or a browser extension.
+//!
+//! Only the origins in `CORS_ALLOWED_ORIGINS` are allowed, with credentials. An entry is either an
+//! exact origin (`https://app.example.com`, `chrome-extension://abcdefgh`), or matches all
+//! subdomains of a host (`https://*.example.com`, which does not match `https://example.com`).
+//! The list is read for every request, so a reload takes effect right away.
+//!
+//! `/oauth` has no CORS headers, so it stays same-origin only.
+
+use ::axum::http::{header, HeaderName, HeaderValue, Method};
+use ::std::time::Duration;
+use ::tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::config::{ConfigValue, SharedConfig};
+use crate::csrf::CSRF_HEADER;
+
+const ALLOWED_METHODS: [Method; 4] = [Method::GET, Method::POST, Method::PUT, Method::DELETE];
+
+/// Browsers send `traceparent` and `tracestate` only if the tracing of the front-end adds them.
+const ALLOWED_HEADERS: [HeaderName; 7] = [
+    header::AUTHORIZATION,
+    header::CONTENT_TYPE,
+    header::IF_MATCH,
+    CSRF_HEADER,
+    HeaderName::from_static("last-event-id"),
+    HeaderName::from_static("traceparent"),
+    HeaderName::from_static("tracestate"),
+];
+
+/// Response headers that front-ends may read, besides the CORS-safelisted ones
+const EXPOSED_HEADERS: [HeaderName; 7] = [
+    header::ETAG,
+    header::RETRY_AFTER,
+    HeaderName::from_static("ratelimit-limit"),
+    HeaderName::from_static("ratelimit-remaining"),
+    HeaderName::from_static("ratelimit-reset"),
+    HeaderName::from_static("ratelimit-policy"),
+    HeaderName::from_static("x-request-id"),
+];
+
+/// The origins that may call `/api`. Empty unless configured, which disallows all but the
+/// same origin.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct AllowedOrigins(Vec<OriginPattern>);
+
+#[derive(Clone, Debug)]
+enum OriginPattern {
+    Exact(String),
+    /// `scheme://*.suffix`, stored as the scheme and `.suffix`
+    Subdomains {
+        scheme: String,
+        suffix: String,
+    },
+}
+
+impl AllowedOrigins {
+    fn allows(&self, origin: &str) -> bool {
+        self.0.iter().any(|pattern| match pattern {
+            OriginPattern::Exact(allowed) => allowed.eq_ignore_ascii_case(origin),
+            OriginPattern::Subdomains { scheme, suffix } => origin
+                .to_ascii_lowercase()
+                .strip_prefix(scheme.as_str())
+                .and_then(|rest| rest.strip_prefix("://"))
+                .and_then(|host| host.strip_suffix(suffix.as_str()))
+                .is_some_and(is_domain),
+        })
+    }
+}
+
+/// A comma-separated list of origins, see the module documentation
+impl ConfigValue for AllowedOrigins {
+    fn parse(value: &str) -> Result<Self, String> {
+        let origins = <Vec<String> as ConfigValue>::parse(value)?;
+        origins
+            .iter()
+            .map(|origin| {
+                let origin = origin.to_ascii_lowercase();
+                let invalid = || {
+                    format!(
+                        "{origin} must be an origin such as https://app.example.com, or \
+                         https://*.example.com for all subdomains"
+                    )
+                };
+                let (scheme, host) = origin.split_once("://").ok_or_else(invalid)?;
+                if scheme.is_empty() {
+                    return Err(invalid());
+                }
+                match host.strip_prefix("*.") {
+                    Some(domain) if is_host(domain) => Ok(OriginPattern::Subdomains {
+                        scheme: scheme.to_string(),
+                        suffix: format!(".{domain}"),
+                    }),
+                    None if is_host(host) => Ok(OriginPattern::Exact(origin.clone())),
+                    _ => Err(invalid()),
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(AllowedOrigins)
+    }
+}
+
+/// A host name, optionally with a port, and no path
+fn is_host(host: &str) -> bool {
+    match host.rsplit_once(':') {
+        Some((domain, port)) => is_domain(domain) && port.parse::<u16>().is_ok(),
+        None => is_domain(host),
+    }
+}
+
+fn is_domain(domain: &str) -> bool {
+    !domain.is_empty()
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-'))
+}
+
+/// The CORS layer of `/api`. `max_age` is how long browsers may cache the answer to a preflight
+/// request.
+pub(crate) fn layer(config: SharedConfig, max_age: Duration) -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::predicate(move |origin: &HeaderValue, _| {
+            origin
+                .to_str()
+                .is_ok_and(|origin| config.current().cors_allowed_origins.allows(origin))
+        }))
+        // Lets browsers send the session cookie. It is `SameSite=Strict`, so only front-ends on
+        // the same site get it, others send an `Authorization` header.
+        .allow_credentials(true)
+        .allow_methods(ALLOWED_METHODS)
+        .allow_headers(ALLOWED_HEADERS)
+        .expose_headers(EXPOSED_HEADERS)
+        .max_age(max_age)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::axum::routing::put;
+    use ::axum::Router;
+    use ::reqwest::StatusCode;
+
+    use super::*;
+    use crate::test_support;
+
+    fn origins(value: &str) -> AllowedOrigins {
+        AllowedOrigins::parse(value).unwrap()
+    }
+
+    #[test]
+    fn subdomain_patterns_match_whole_labels_of_the_same_scheme() {
+        let allowed = origins("https://*.example.com");
+        for origin in ["https://app.example.com", "https://a.b.example.com"] {
+            assert!(allowed.allows(origin), "{origin}");
+        }
+        for origin in [
+            "https://example.com",
+            "https://evilexample.com",
+            "https://app.example.com.evil.org",
+            "http://app.example.com",
+            "https://app.example.com:8443",
+            "https://.example.com",
+            "https://evil.org/.example.com",
+            "null",
+        ] {
+            assert!(!allowed.allows(origin), "{origin}");
+        }
+    }
+
+    #[test]
+    fn exact_origins_include_scheme_and_port() {
+        let allowed =
+            origins("https://app.example.com, http://localhost:8000, chrome-extension://abcdefgh");
+        for origin in [
+            "https://app.example.com",
+            "HTTPS://App.Example.com",
+            "http://localhost:8000",
+            "chrome-extension://abcdefgh",
+        ] {
+            assert!(allowed.allows(origin), "{origin}");
+        }
+        for origin in [
+            "http://app.example.com",
+            "https://app.example.com:443",
+            "http://localhost",
+            "http://localhost:8001",
+            "null",
+            "",
+        ] {
+            assert!(!allowed.allows(origin), "{origin}");
+        }
+
+        let allowed = origins("https://*.example.com:8443");
+        assert!(allowed.allows("https://app.example.com:8443"));
+        assert!(!allowed.allows("https://app.example.com"));
+        assert!(!AllowedOrigins::default().allows("https://app.example.com"));
+    }
+
+    #[test]
+    fn invalid_origins_are_rejected() {
+        for value in [
+            "null",
+            "app.example.com",
+            "://app.example.com",
+            "https://",
+            "https://app.example.com/",
+            "https://app.example.com/api",
+            "https://app.example.com:http",
+            "https://*.",
+            "https://*",
+            "https://app.*.example.com",
+            "https://user@app.example.com",
+        ] {
+            assert!(AllowedOrigins::parse(value).is_err(), "{value} is accepted");
+        }
+    }
+
+    #[tokio::test]
+    async fn preflight_requests_allow_credentials_for_allowed_origins_only() {
+        let config = SharedConfig::for_tests(&[
+            ("GITHUB_APP_CLIENT_SECRET", "secret"),
+            ("CORS_ALLOWED_ORIGINS", "https://*.example.com"),
+        ]);
+        let router = Router::new()
+            .route("/preferences", put(|| async {}))
+            .layer(layer(config, Duration::from_secs(600)));
+        let url = format!("http://{}/preferences", test_support::serve(router).await);
+        let preflight = |origin: &'static str| {
+            reqwest::Client::new()
+                .request(Method::OPTIONS, &url)
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+                .header(
+                    header::ACCESS_CONTROL_REQUEST_HEADERS,
+                    "content-type, if-match, x-csrf-token",
+                )
+                .send()
+        };
+
+        let response = preflight("https://app.example.com").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+        let allowed_methods = headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+            .to_str()
+            .unwrap();
+        assert!(allowed_methods.contains("PUT"), "{allowed_methods}");
+        let allowed_headers = headers[header::ACCESS_CONTROL_ALLOW_HEADERS]
+            .to_str()
+            .unwrap();
+        for name in ["content-type", "if-match", "x-csrf-token"] {
+            assert!(allowed_headers.contains(name), "{allowed_headers}");
+        }
+        assert!(headers[header::VARY].to_str().unwrap().contains("origin"));
+
+        for origin in ["https://evilexample.com", "http://app.example.com", "null"] {
+            let response = preflight(origin).await.unwrap();
+            let headers = response.headers();
+            assert!(
+                !headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN),
+                "{origin}"
+            );
+        }
+    }
+}
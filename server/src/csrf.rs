@@ -0,0 +1,252 @@
+//! Protection of `/api` against cross-site request forgery (CSRF).
+//!
+//! Browsers attach the session cookie to requests that other sites trigger, so unsafe requests
+//! (`/api` allows `POST`, `PUT` and `DELETE`, see `crate::cors`) that carry it must prove that they
+//! come from a front-end of ours. They pass [`protect`] if either
+//!
+//! - the browser marks them as `Sec-Fetch-Site: same-origin`, or
+//! - they repeat the token of the `csrf-token` cookie in an `X-CSRF-Token` header ("double
+//!   submit"). Other sites can neither read the cookie nor set the header.
+//!
+//! The SPA finds the token in the configuration in `index.html` (`csrfToken`), other front-ends
+//! get it from `GET /api/csrf-token`. Both set the cookie if it is missing.
+//!
+//! Requests that authenticate with an `Authorization: Bearer` header, or without the session
+//! cookie, are not at risk: browsers do not add that header on their own. Other `Authorization`
+//! schemes do not exempt a request, as `crate::auth` then falls back to the cookie.
+
+use ::axum::extract::Request;
+use ::axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method};
+use ::axum::middleware::Next;
+use ::axum::response::{IntoResponse, Response};
+use ::axum::Json;
+use ::serde::Serialize;
+use ::tracing::warn;
+
+use crate::auth::{self, ACCESS_TOKEN_COOKIE};
+use crate::route::api::ApiError;
+
+/// Name of the cookie with the token. It is `HttpOnly`: front-ends get the token from
+/// `index.html` or `/api/csrf-token`.
+const CSRF_COOKIE: &str = "csrf-token";
+
+/// Where unsafe requests repeat the token.
+pub(crate) const CSRF_HEADER: HeaderName = HeaderName::from_static("x-csrf-token");
+
+/// The token of a browser session. A new one is only issued when the cookie is missing.
+#[derive(Clone, Debug)]
+pub(crate) struct CsrfToken(pub String);
+
+impl CsrfToken {
+    /// Returns the token of the cookie, or else a new token and the `Set-Cookie` header for it.
+    pub(crate) fn for_request(headers: &HeaderMap) -> (Self, Option<(HeaderName, HeaderValue)>) {
+        match auth::cookie(headers, CSRF_COOKIE).filter(|token| is_token(token)) {
+            Some(token) => (CsrfToken(token.to_string()), None),
+            None => {
+                let token = format!("{:032x}", ::rand::random::<u128>());
+                let cookie = format!("{CSRF_COOKIE}={token}; path=/; HttpOnly; SameSite=Strict");
+                // The cookie consists of visible ASCII characters only.
+                let set_cookie = (header::SET_COOKIE, HeaderValue::try_from(cookie).unwrap());
+                (CsrfToken(token), Some(set_cookie))
+            }
+        }
+    }
+}
+
+/// As issued by [`CsrfToken::for_request`]
+fn is_token(token: &str) -> bool {
+    token.len() == 32 && token.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[derive(Serialize)]
+struct CsrfTokenResponse {
+    token: String,
+}
+
+/// `GET /api/csrf-token`, for front-ends other than the SPA
+pub(crate) async fn get_token(headers: HeaderMap) -> Response {
+    let (CsrfToken(token), set_cookie) = CsrfToken::for_request(&headers);
+    (
+        set_cookie.into_iter().collect::<HeaderMap>(),
+        Json(CsrfTokenResponse { token }),
+    )
+        .into_response()
+}
+
+/// A middleware that rejects unsafe requests that may be forged, with
+/// [`ApiError::CsrfTokenInvalid`].
+pub(crate) async fn protect(request: Request, next: Next) -> Response {
+    if is_at_risk(&request) && !is_same_origin(request.headers()) && !has_token(request.headers()) {
+        warn!(
+            method = %request.method(),
+            path = request.uri().path(),
+            "Rejected a request without a valid CSRF token"
+        );
+        return ApiError::CsrfTokenInvalid.into_response();
+    }
+    next.run(request).await
+}
+
+/// Unsafe requests that the browser authenticates with the session cookie
+fn is_at_risk(request: &Request) -> bool {
+    let headers = request.headers();
+    !matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    ) && auth::bearer_token(headers).is_none()
+        && auth::cookie(headers, ACCESS_TOKEN_COOKIE).is_some()
+}
+
+/// Set by all current browsers, and not by other sites
+fn is_same_origin(headers: &HeaderMap) -> bool {
+    headers
+        .get("sec-fetch-site")
+        .is_some_and(|value| value == "same-origin")
+}
+
+fn has_token(headers: &HeaderMap) -> bool {
+    match (
+        auth::cookie(headers, CSRF_COOKIE),
+        headers
+            .get(CSRF_HEADER)
+            .and_then(|value| value.to_str().ok()),
+    ) {
+        (Some(expected), Some(given)) => {
+            is_token(expected) && auth::constant_time_eq(given.as_bytes(), expected.as_bytes())
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::axum::middleware;
+    use ::axum::routing::any;
+    use ::axum::Router;
+    use ::reqwest::StatusCode;
+
+    use super::*;
+    use crate::test_support;
+
+    const TOKEN: &str = "0123456789abcdef0123456789abcdef";
+
+    const SIGNED_IN: &str = "github-access-token=gho_token";
+
+    /// Sends a request to a route behind [`protect`], and returns the status.
+    async fn send(method: Method, headers: &[(HeaderName, &str)]) -> StatusCode {
+        let router = Router::new()
+            .route("/", any(|| async {}))
+            .layer(middleware::from_fn(protect));
+        let url = format!("http://{}/", test_support::serve(router).await);
+        let mut request = reqwest::Client::new().request(method, url);
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        request.send().await.unwrap().status()
+    }
+
+    fn cookies(csrf_token: &str) -> String {
+        format!("{SIGNED_IN}; {CSRF_COOKIE}={csrf_token}")
+    }
+
+    #[tokio::test]
+    async fn safe_methods_and_requests_without_the_session_cookie_pass() {
+        let signed_in = [(header::COOKIE, SIGNED_IN)];
+        for method in [Method::GET, Method::HEAD, Method::OPTIONS] {
+            assert_eq!(
+                send(method.clone(), &signed_in).await,
+                StatusCode::OK,
+                "{method}"
+            );
+        }
+        for method in [Method::POST, Method::PUT, Method::DELETE] {
+            assert_eq!(send(method.clone(), &[]).await, StatusCode::OK, "{method}");
+            let anonymous = [(header::COOKIE, "csrf-token=x; theme=dark")];
+            assert_eq!(
+                send(method.clone(), &anonymous).await,
+                StatusCode::OK,
+                "{method}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn bearer_tokens_are_exempt() {
+        let headers = [
+            (header::COOKIE, SIGNED_IN),
+            (header::AUTHORIZATION, "Bearer gho_token"),
+        ];
+        assert_eq!(send(Method::POST, &headers).await, StatusCode::OK);
+        // With other schemes, `crate::auth` falls back to the cookie.
+        let headers = [
+            (header::COOKIE, SIGNED_IN),
+            (header::AUTHORIZATION, "Basic dXNlcjpwYXNz"),
+        ];
+        assert_eq!(send(Method::POST, &headers).await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn same_origin_requests_pass() {
+        let sec_fetch_site = HeaderName::from_static("sec-fetch-site");
+        let headers = [
+            (header::COOKIE, SIGNED_IN),
+            (sec_fetch_site.clone(), "same-origin"),
+        ];
+        assert_eq!(send(Method::PUT, &headers).await, StatusCode::OK);
+        for site in ["same-site", "cross-site", "none"] {
+            let headers = [(header::COOKIE, SIGNED_IN), (sec_fetch_site.clone(), site)];
+            assert_eq!(
+                send(Method::PUT, &headers).await,
+                StatusCode::FORBIDDEN,
+                "{site}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn unsafe_requests_must_repeat_the_token_of_the_cookie() {
+        let cookies = cookies(TOKEN);
+        let headers = [(header::COOKIE, cookies.as_str()), (CSRF_HEADER, TOKEN)];
+        assert_eq!(send(Method::POST, &headers).await, StatusCode::OK);
+
+        let other_token = "fedcba9876543210fedcba9876543210";
+        let headers = [
+            (header::COOKIE, cookies.as_str()),
+            (CSRF_HEADER, other_token),
+        ];
+        assert_eq!(send(Method::POST, &headers).await, StatusCode::FORBIDDEN);
+        let headers = [(header::COOKIE, cookies.as_str())];
+        assert_eq!(send(Method::DELETE, &headers).await, StatusCode::FORBIDDEN);
+        let headers = [(header::COOKIE, SIGNED_IN), (CSRF_HEADER, TOKEN)];
+        assert_eq!(send(Method::PUT, &headers).await, StatusCode::FORBIDDEN);
+        // A cookie that another site managed to set is no token of ours.
+        let cookies = self::cookies("forged");
+        let headers = [(header::COOKIE, cookies.as_str()), (CSRF_HEADER, "forged")];
+        assert_eq!(send(Method::POST, &headers).await, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn tokens_are_only_issued_when_the_cookie_is_missing() {
+        let mut headers = HeaderMap::new();
+        let cookies = cookies(TOKEN);
+        headers.insert(header::COOKIE, HeaderValue::try_from(cookies).unwrap());
+        let (CsrfToken(token), set_cookie) = CsrfToken::for_request(&headers);
+        assert_eq!(token, TOKEN);
+        assert!(set_cookie.is_none());
+
+        for cookie in [None, Some("csrf-token=forged")] {
+            let mut headers = HeaderMap::new();
+            if let Some(cookie) = cookie {
+                headers.insert(header::COOKIE, HeaderValue::from_static(cookie));
+            }
+            let (CsrfToken(token), set_cookie) = CsrfToken::for_request(&headers);
+            assert!(is_token(&token), "{token}");
+            let (name, value) = set_cookie.unwrap();
+            assert_eq!(name, header::SET_COOKIE);
+            assert_eq!(
+                value,
+                format!("csrf-token={token}; path=/; HttpOnly; SameSite=Strict")
+            );
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! The client for outbound HTTP calls, shared by all handlers so that connections are reused.
+//!
+//! Every call goes to an [`Endpoint`] of an [`Upstream`], and gets a span with the method, host,
+//! status and latency, which carries the trace context to the upstream. Idempotent calls are
+//! retried with jittered exponential backoff when the connection fails, times out, or the
+//! upstream is temporarily unavailable.
+//!
+//! The base URL of each upstream can be overridden (e.g. `GITHUB_API_BASE_URL`), so that GitHub
+//! traffic can be pointed at a local fake server.
+
+use ::axum::http::{Method, StatusCode};
+use ::reqwest::{RequestBuilder, Response, Url};
+use ::std::sync::Arc;
+use ::std::time::{Duration, Instant};
+use ::tracing::{debug, info_span, warn, Instrument};
+
+use crate::limits::LimitsConfig;
+use crate::metrics;
+use crate::tracing::trace_context_headers;
+
+/// GitHub requires a `User-Agent` header.
+const USER_AGENT: &str = concat!("elm-on-shuttle/", env!("CARGO_PKG_VERSION"));
+
+/// Including the first one
+const MAX_ATTEMPTS: u32 = 3;
+
+/// The backoff before the first retry is random, up to this long, and doubles with every retry.
+const BASE_BACKOFF: Duration = Duration::from_millis(200);
+
+/// The services the server calls.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Upstream {
+    /// `https://github.com`, for the OAuth flow
+    GitHub,
+    /// `https://api.github.com`
+    GitHubApi,
+}
+
+/// The base URL of every [`Upstream`].
+#[derive(Clone, Debug)]
+pub(crate) struct UpstreamUrls {
+    pub github: Url,
+    pub github_api: Url,
+}
+
+impl UpstreamUrls {
+    fn base_url(&self, upstream: Upstream) -> &Url {
+        match upstream {
+            Upstream::GitHub => &self.github,
+            Upstream::GitHubApi => &self.github_api,
+        }
+    }
+}
+
+/// An operation of an upstream.
+#[derive(Clone, Debug)]
+pub(crate) struct Endpoint {
+    /// Labels the metrics, e.g. `github_request_duration_seconds`
+    pub name: &'static str,
+    pub upstream: Upstream,
+    pub method: Method,
+    /// Appended to the base URL of the upstream, which may have a path of its own
+    pub path: &'static str,
+}
+
+impl Endpoint {
+    /// Whether repeating the call has no further effect, which makes it safe to retry.
+    fn is_idempotent(&self) -> bool {
+        matches!(
+            self.method,
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE
+        )
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct HttpClient {
+    client: reqwest::Client,
+    urls: Arc<UpstreamUrls>,
+}
+
+impl HttpClient {
+    /// Uses the outbound timeouts of `limits`.
+    pub(crate) fn new(limits: &LimitsConfig, urls: UpstreamUrls) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .connect_timeout(limits.outbound_connect_timeout)
+            .timeout(limits.outbound_request_timeout)
+            .build()?;
+        Ok(HttpClient {
+            client,
+            urls: Arc::new(urls),
+        })
+    }
+
+    /// Where `endpoint` is, e.g. for error messages.
+    pub(crate) fn url(&self, endpoint: &Endpoint) -> String {
+        let base_url = self.urls.base_url(endpoint.upstream).as_str();
+        format!("{}{}", base_url.trim_end_matches('/'), endpoint.path)
+    }
+
+    /// Calls `endpoint`. `build` adds headers, a query or a body.
+    pub(crate) async fn send(
+        &self,
+        endpoint: &Endpoint,
+        build: impl FnOnce(RequestBuilder) -> RequestBuilder,
+    ) -> reqwest::Result<Response> {
+        let url = self.url(endpoint);
+        let mut request = build(self.client.request(endpoint.method.clone(), &url)).build()?;
+        let span = info_span!(
+            "outbound_request",
+            otel.kind = "client",
+            endpoint = endpoint.name,
+            method = %endpoint.method,
+            host = request.url().host_str().unwrap_or_default(),
+            url,
+            status = ::tracing::field::Empty,
+            latency_ms = ::tracing::field::Empty,
+        );
+        request.headers_mut().extend(trace_context_headers(&span));
+
+        let start = Instant::now();
+        let mut attempt = 1;
+        let result = loop {
+            // Requests with a streaming body cannot be cloned, and are not retried.
+            let retry = if endpoint.is_idempotent() && attempt < MAX_ATTEMPTS {
+                request.try_clone()
+            } else {
+                None
+            };
+            let result = self.client.execute(request).instrument(span.clone()).await;
+            let Some(next_request) = retry.filter(|_| is_transient(&result)) else {
+                break result;
+            };
+            let backoff = backoff(attempt);
+            span.in_scope(|| {
+                warn!(
+                    attempt,
+                    backoff_ms = backoff.as_millis() as u64,
+                    "Transient failure, retrying: {}",
+                    describe(&result)
+                )
+            });
+            metrics::record_github_request_retry(endpoint.name);
+            tokio::time::sleep(backoff).await;
+            request = next_request;
+            attempt += 1;
+        };
+
+        let latency = start.elapsed();
+        metrics::record_github_request(endpoint.name, latency);
+        let latency_ms = latency.as_millis() as u64;
+        span.record("latency_ms", latency_ms);
+        match &result {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                span.record("status", status);
+                span.in_scope(|| debug!(status, latency_ms, "Outbound request completed"));
+            }
+            Err(e) => {
+                metrics::record_github_request_error(endpoint.name, e);
+                span.in_scope(|| warn!(latency_ms, "Outbound request failed: {e}"));
+            }
+        }
+        result
+    }
+}
+
+/// Failures that are likely to go away by themselves
+fn is_transient(result: &reqwest::Result<Response>) -> bool {
+    match result {
+        Ok(response) => matches!(
+            response.status(),
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+        ),
+        Err(e) => e.is_connect() || e.is_timeout(),
+    }
+}
+
+fn describe(result: &reqwest::Result<Response>) -> String {
+    match result {
+        Ok(response) => format!("responded with {}", response.status()),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// "Full jitter": random, up to an exponentially growing maximum. This keeps clients that failed
+/// at the same time from retrying in lockstep.
+fn backoff(attempt: u32) -> Duration {
+    let max = BASE_BACKOFF * 2u32.pow(attempt - 1);
+    max.mul_f64(::rand::random())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use ::axum::routing::get;
+    use ::axum::Router;
+    use ::std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::test_support;
+
+    const FAKE_GET: Endpoint = Endpoint {
+        name: "fake_get",
+        upstream: Upstream::GitHubApi,
+        method: Method::GET,
+        path: "/flaky",
+    };
+
+    const FAKE_POST: Endpoint = Endpoint {
+        name: "fake_post",
+        upstream: Upstream::GitHub,
+        method: Method::POST,
+        path: "/flaky",
+    };
+
+    /// Serves `/prefix/flaky`, which fails with `503` `failures` times before it succeeds, and
+    /// returns its base URL and the number of calls.
+    async fn fake_upstream(failures: u32) -> (Url, Arc<AtomicU32>) {
+        let calls = Arc::new(AtomicU32::new(0));
+        let handler = {
+            let calls = calls.clone();
+            move || async move {
+                if calls.fetch_add(1, Ordering::SeqCst) < failures {
+                    StatusCode::SERVICE_UNAVAILABLE
+                } else {
+                    StatusCode::OK
+                }
+            }
+        };
+        let router = Router::new().route("/prefix/flaky", get(handler.clone()).post(handler));
+        let url = format!("http://{}/prefix/", test_support::serve(router).await);
+        (url.parse().unwrap(), calls)
+    }
+
+    pub(crate) fn client(base_url: Url) -> HttpClient {
+        let limits = LimitsConfig {
+            request_timeout: Duration::from_secs(10),
+            oauth_request_timeout: Duration::from_secs(30),
+            max_request_body_bytes: 65536,
+            max_concurrent_requests: 512,
+            outbound_connect_timeout: Duration::from_secs(5),
+            outbound_request_timeout: Duration::from_secs(5),
+        };
+        let urls = UpstreamUrls {
+            github: base_url.clone(),
+            github_api: base_url,
+        };
+        HttpClient::new(&limits, urls).unwrap()
+    }
+
+    #[tokio::test]
+    async fn calls_the_overridden_base_url() {
+        let (base_url, calls) = fake_upstream(0).await;
+        let client = client(base_url.clone());
+        assert_eq!(client.url(&FAKE_GET), format!("{base_url}flaky"));
+        let response = client.send(&FAKE_GET, |request| request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_idempotent_calls_on_transient_failures() {
+        let (base_url, calls) = fake_upstream(2).await;
+        let response = client(base_url)
+            .send(&FAKE_GET, |request| request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_last_attempt() {
+        let (base_url, calls) = fake_upstream(MAX_ATTEMPTS).await;
+        let response = client(base_url)
+            .send(&FAKE_GET, |request| request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(calls.load(Ordering::SeqCst), MAX_ATTEMPTS);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_other_calls() {
+        let (base_url, calls) = fake_upstream(1).await;
+        let response = client(base_url)
+            .send(&FAKE_POST, |request| request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,96 @@
+//! Limits that keep slow clients, huge bodies and traffic spikes from tying up the server:
+//!
+//! - a timeout per area (see [`enforce_timeout`]), after which the request is answered with
+//!   `503 Service Unavailable`
+//! - a default maximum body size, for routes that do not set their own with `DefaultBodyLimit`
+//! - a maximum number of concurrent requests, beyond which requests are shed with
+//!   `503 Service Unavailable` and a `Retry-After` header (see [`shed_load`])
+//! - connect and request timeouts for outbound HTTP calls (see `crate::http_client`)
+//!
+//! The limits apply to the response head. WebSocket sessions and event streams run longer, and
+//! do not count towards the concurrent requests once they have been established.
+
+use ::axum::extract::{Request, State};
+use ::axum::middleware::Next;
+use ::axum::response::{IntoResponse, Response};
+use ::std::sync::Arc;
+use ::std::time::Duration;
+use ::tokio::sync::Semaphore;
+use ::tracing::warn;
+
+use crate::metrics::{self, InFlightRequestGuard};
+use crate::route::api::ApiError;
+
+/// What shed requests are told. Load spikes tend to be short.
+const SHED_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Settings of all limits.
+#[derive(Clone, Debug)]
+pub(crate) struct LimitsConfig {
+    /// For every request, unless its area has its own timeout
+    pub request_timeout: Duration,
+    /// For `/oauth`, which waits for GitHub, possibly more than once
+    pub oauth_request_timeout: Duration,
+    /// For routes without a limit of their own
+    pub max_request_body_bytes: usize,
+    pub max_concurrent_requests: usize,
+    /// How long outbound HTTP calls may take to connect
+    pub outbound_connect_timeout: Duration,
+    /// How long outbound HTTP calls may take from connecting until the response body has been
+    /// read
+    pub outbound_request_timeout: Duration,
+}
+
+/// The timeout of an area, for [`enforce_timeout`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RequestTimeout {
+    /// Labels the metrics
+    pub area: &'static str,
+    pub duration: Duration,
+}
+
+/// A middleware that answers with [`ApiError::Timeout`] if the handler takes longer than
+/// `timeout`. Use it with [`axum::middleware::from_fn_with_state`].
+pub(crate) async fn enforce_timeout(
+    State(timeout): State<RequestTimeout>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match tokio::time::timeout(timeout.duration, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            warn!(area = timeout.area, "Request timed out");
+            metrics::record_request_timeout(timeout.area);
+            ApiError::Timeout(timeout.duration).into_response()
+        }
+    }
+}
+
+/// Counts the requests in flight across all areas that share it.
+#[derive(Clone)]
+pub(crate) struct ConcurrencyLimit(Arc<Semaphore>);
+
+impl ConcurrencyLimit {
+    pub(crate) fn new(max_concurrent_requests: usize) -> Self {
+        ConcurrencyLimit(Arc::new(Semaphore::new(max_concurrent_requests)))
+    }
+}
+
+/// A middleware that answers with [`ApiError::Overloaded`] right away, rather than queueing, if
+/// the limit has been reached. Use it with [`axum::middleware::from_fn_with_state`].
+pub(crate) async fn shed_load(
+    State(limit): State<ConcurrencyLimit>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Ok(_permit) = limit.0.clone().try_acquire_owned() else {
+        warn!("Too many concurrent requests, shedding load");
+        metrics::record_shed_request();
+        return ApiError::Overloaded {
+            retry_after: SHED_RETRY_AFTER,
+        }
+        .into_response();
+    };
+    let _in_flight = InFlightRequestGuard::new();
+    next.run(request).await
+}
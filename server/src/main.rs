@@ -1,24 +1,203 @@
-use ::axum::Router;
-use ::shuttle_axum::ShuttleAxum;
+use ::axum::extract::DefaultBodyLimit;
+use ::axum::{middleware, Router};
+use ::common::tracing::LogFilter;
+#[cfg(not(feature = "standalone"))]
 use ::shuttle_runtime::{SecretStore as ShuttleSecretStore, Secrets as ShuttleSecrets};
+use ::std::sync::Arc;
+use ::tracing::info;
+#[cfg(not(feature = "standalone"))]
+use ::tracing::warn;
 
+use self::auth::Authenticator;
+use self::broker::Broker;
+use self::config::SharedConfig;
+use self::http_client::HttpClient;
+use self::limits::{ConcurrencyLimit, RequestTimeout};
+use self::rate_limit::{Budget, InMemoryRateLimitStore, RateLimitStore, RateLimiter};
+use self::route::admin;
 use self::route::api;
+use self::route::health;
 use self::route::oauth;
 use self::route::spa;
+use self::security::{Area, SecurityHeaders};
+use self::shutdown::Shutdown;
+#[cfg(not(feature = "standalone"))]
+use self::shuttle::{ShuttleServer, ShuttleService};
 
+mod auth;
+mod broker;
+mod config;
+mod cors;
+mod csrf;
+mod http_client;
+mod limits;
+mod metrics;
+mod rate_limit;
+mod redact;
 mod route;
-mod secrets;
+mod security;
+mod shutdown;
+#[cfg(not(feature = "standalone"))]
+mod shuttle;
+#[cfg(feature = "standalone")]
+mod standalone;
+#[cfg(test)]
+mod test_support;
 mod tracing;
 
+/// Every OAuth callback makes an outbound request to GitHub, so it gets a tight budget.
+const OAUTH_BUDGET: Budget = Budget {
+    name: "oauth",
+    burst: 5,
+    per_minute: 10,
+};
+
+const API_BUDGET: Budget = Budget {
+    name: "api",
+    burst: 60,
+    per_minute: 300,
+};
+
+/// A healthy UI reports little. This keeps a broken one from flooding the log.
+const CLIENT_LOGS_BUDGET: Budget = Budget {
+    name: "client-logs",
+    burst: 10,
+    per_minute: 20,
+};
+
+#[cfg(not(feature = "standalone"))]
 #[shuttle_runtime::main]
-async fn main(#[ShuttleSecrets] secret_store: ShuttleSecretStore) -> ShuttleAxum {
-    tracing::init();
-    let secrets = secrets::try_from(secret_store)?;
+async fn main(#[ShuttleSecrets] secret_store: ShuttleSecretStore) -> ShuttleServer {
+    let (log_filter, otlp_export) = tracing::init();
+    let secrets = config::Layer::secret_store(&secret_store);
+    let (config, settings) = config::load(Some(secrets.clone()))?;
+    // Shuttle only reads the secret store at startup.
+    let config = SharedConfig::new(config, settings, move || Ok(Some(secrets.clone())));
+    if config.current().trusted_proxy_header.is_none() {
+        // Shuttle does not pass the peer address on, see `rate_limit::RateLimiter`.
+        warn!(
+            "TRUSTED_PROXY_HEADER is not set, so all anonymous clients share one rate-limit \
+             bucket. Set it to the header in which Shuttle's proxy passes the client address, \
+             e.g. `X-Forwarded-For`."
+        );
+    }
+    let shutdown = Shutdown::on_signal();
+    let router = app(config, log_filter, shutdown.clone())?;
+    Ok(ShuttleService {
+        router,
+        shutdown,
+        otlp_export,
+    })
+}
+
+/// Runs the same app without the Shuttle runtime, see [`standalone`]. With `--print-config`, only
+/// prints the configuration.
+#[cfg(feature = "standalone")]
+#[::tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let (log_filter, otlp_export) = tracing::init();
+    let (config, settings) = config::load(standalone::secrets_file()?)?;
+    if std::env::args().any(|arg| arg == "--print-config") {
+        print!("{settings}");
+        return Ok(());
+    }
+    let config = SharedConfig::new(config, settings, standalone::secrets_file);
+    let shutdown = Shutdown::on_signal();
+    let router = app(config, log_filter, shutdown.clone())?;
+    let served = standalone::serve(router, shutdown).await;
+    if let Some(otlp_export) = otlp_export {
+        otlp_export.shutdown().await;
+    }
+    served
+}
+
+fn app(config: SharedConfig, log_filter: LogFilter, shutdown: Shutdown) -> anyhow::Result<Router> {
+    let metrics_handle = metrics::init()?;
+    config::reload_on_sighup(config.clone());
+    // Only read at startup, see `config::RESTART_KEYS`
+    let startup_config = config.current();
+
+    let limits = &startup_config.limits;
+    let http_client = HttpClient::new(limits, startup_config.upstream_urls.clone())?;
+    // Shared, so that the rate limiter knows the tokens that the API has verified
+    let authenticator = Authenticator::new(http_client.clone());
+
+    let rate_limit_store: Arc<dyn RateLimitStore> = Arc::new(InMemoryRateLimitStore::default());
+    let rate_limiter = |budget| {
+        RateLimiter::new(
+            budget,
+            rate_limit_store.clone(),
+            startup_config.trusted_proxy_header.clone(),
+            authenticator.clone(),
+        )
+    };
+    let rate_limited =
+        |budget| middleware::from_fn_with_state(rate_limiter(budget), rate_limit::enforce);
+    let security_headers = |area| {
+        middleware::from_fn_with_state(
+            SecurityHeaders::new(area, startup_config.security.clone(), config.clone()),
+            security::set_headers,
+        )
+    };
+    let timeout = |area, duration| {
+        middleware::from_fn_with_state(RequestTimeout { area, duration }, limits::enforce_timeout)
+    };
+    // Shared by all areas. `/health`, `/metrics` and `/admin` stay reachable under load.
+    let concurrency_limit = ConcurrencyLimit::new(limits.max_concurrent_requests);
+    let load_shedding =
+        || middleware::from_fn_with_state(concurrency_limit.clone(), limits::shed_load);
+
+    #[cfg(not(feature = "embed-ui"))]
+    let ui_files = spa::UiFiles::Directory(["ui", "dist"].iter().collect());
+    #[cfg(feature = "embed-ui")]
+    let ui_files = spa::UiFiles::Embedded;
+    let preferences: Arc<dyn api::PreferencesStore> =
+        Arc::new(api::InMemoryPreferencesStore::default());
 
     let router = Router::new()
-        .nest("/api", api::router())
-        .nest("/oauth", oauth::router(secrets.github_app_client_secret))
-        .nest_service("/", spa::serve_dir(["ui", "dist"].iter().collect()));
+        .merge(metrics::router(metrics_handle, config.clone()))
+        .merge(health::router(health::Readiness {
+            ui_files: ui_files.clone(),
+            preferences: preferences.clone(),
+        }))
+        .nest(
+            "/api",
+            api::router(api::ApiState {
+                authenticator: authenticator.clone(),
+                preferences,
+                broker: Broker::default(),
+                client_logs_limiter: rate_limiter(CLIENT_LOGS_BUDGET),
+                shutdown,
+            })
+            .layer(rate_limited(API_BUDGET))
+            .layer(timeout("api", limits.request_timeout))
+            .layer(load_shedding())
+            // Outermost, so that preflight requests are neither limited nor shed
+            .layer(cors::layer(config.clone(), startup_config.cors_max_age)),
+        )
+        .nest(
+            "/oauth",
+            oauth::router(config.clone(), http_client)
+                .layer(rate_limited(OAUTH_BUDGET))
+                .layer(timeout("oauth", limits.oauth_request_timeout))
+                .layer(load_shedding())
+                .layer(security_headers(Area::OAuth)),
+        )
+        .nest("/admin", admin::router(log_filter, config.clone()))
+        // Everything but the SPA, and the areas with their own policy
+        .layer(security_headers(Area::Api))
+        .nest_service(
+            "/",
+            spa::router(ui_files, config.clone())
+                .layer(timeout("spa", limits.request_timeout))
+                .layer(load_shedding())
+                .layer(security_headers(Area::Spa)),
+        )
+        // Routes with a limit of their own override it.
+        .layer(DefaultBodyLimit::max(limits.max_request_body_bytes));
+    if startup_config.admin_bearer_token.is_none() {
+        info!("/admin is disabled until ADMIN_BEARER_TOKEN is set");
+    }
 
-    Ok(tracing::wrap_router(router).into())
+    Ok(tracing::wrap_router(metrics::wrap_router(router)))
 }
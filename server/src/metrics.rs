@@ -0,0 +1,174 @@
+//! Prometheus metrics: recording helpers, the HTTP middleware and the `/metrics` endpoint.
+
+use ::axum::extract::{MatchedPath, Request};
+use ::axum::http::{header, HeaderMap, StatusCode};
+use ::axum::middleware::{self, Next};
+use ::axum::response::{IntoResponse, Response};
+use ::axum::routing::get;
+use ::axum::Router;
+use ::metrics::{counter, gauge, histogram};
+use ::metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use ::std::time::{Duration, Instant};
+
+use crate::auth;
+use crate::config::SharedConfig;
+
+/// Latency buckets, in seconds, for all `…_duration_seconds` histograms.
+const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// How often histograms are compacted. Without this, their memory use would grow unbounded.
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Installs the global metrics recorder. Must be called once, from within the tokio runtime.
+pub(crate) fn init() -> anyhow::Result<PrometheusHandle> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Suffix("duration_seconds".to_string()),
+            DURATION_BUCKETS,
+        )?
+        .install_recorder()?;
+    let upkeep_handle = handle.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            upkeep_handle.run_upkeep();
+        }
+    });
+    Ok(handle)
+}
+
+/// Serves the metrics in the Prometheus text format at `/metrics`. If `METRICS_BEARER_TOKEN` is
+/// set, scrapers must send it in an `Authorization: Bearer` header.
+pub(crate) fn router(handle: PrometheusHandle, config: SharedConfig) -> Router<()> {
+    Router::new().route(
+        "/metrics",
+        get(move |headers: HeaderMap| render(handle, config, headers)),
+    )
+}
+
+async fn render(handle: PrometheusHandle, config: SharedConfig, headers: HeaderMap) -> Response {
+    if let Some(expected) = &config.current().metrics_bearer_token {
+        if !auth::has_bearer_token(&headers, expected) {
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+            )
+                .into_response();
+        }
+    }
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        handle.render(),
+    )
+        .into_response()
+}
+
+/// Records `http_requests_total` and `http_request_duration_seconds` for every request that
+/// matches a route.
+pub(crate) fn wrap_router(router: Router) -> Router {
+    // A route layer runs after routing, when `MatchedPath` is known. The matched path (with
+    // placeholders not filled in) keeps the number of label values small.
+    router.route_layer(middleware::from_fn(track_http))
+}
+
+async fn track_http(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let path =
+        request
+            .extensions()
+            .get::<MatchedPath>()
+            .map_or("unmatched".to_string(), |matched_path| {
+                // Services nested with `nest_service` match a private catch-all parameter.
+                matched_path
+                    .as_str()
+                    .replace("*__private__axum_nest_tail_param", "*")
+            });
+    let response = next.run(request).await;
+    let labels = [
+        ("method", method),
+        ("path", path),
+        ("status", status_class(response.status())),
+    ];
+    counter!("http_requests_total", &labels).increment(1);
+    histogram!("http_request_duration_seconds", &labels).record(start.elapsed());
+    response
+}
+
+fn status_class(status: StatusCode) -> String {
+    format!("{}xx", status.as_u16() / 100)
+}
+
+/// Counts the outcome of a token request to GitHub in the OAuth flow.
+pub(crate) fn record_oauth_outcome(outcome: &'static str) {
+    counter!("oauth_token_responses_total", "outcome" => outcome).increment(1);
+}
+
+/// Records the latency of a request to GitHub. `endpoint` names the called service.
+pub(crate) fn record_github_request(endpoint: &'static str, duration: Duration) {
+    histogram!("github_request_duration_seconds", "endpoint" => endpoint).record(duration);
+}
+
+/// Counts a retry of a request to GitHub, after a transient failure.
+pub(crate) fn record_github_request_retry(endpoint: &'static str) {
+    counter!("github_request_retries_total", "endpoint" => endpoint).increment(1);
+}
+
+/// Counts a request to GitHub that got no response, after all retries. `error` is `timeout`,
+/// `connect` or `other`.
+pub(crate) fn record_github_request_error(endpoint: &'static str, error: &reqwest::Error) {
+    let kind = if error.is_timeout() {
+        "timeout"
+    } else if error.is_connect() {
+        "connect"
+    } else {
+        "other"
+    };
+    counter!("github_request_errors_total", "endpoint" => endpoint, "error" => kind).increment(1);
+}
+
+/// Counts a request that took longer than the timeout of its area.
+pub(crate) fn record_request_timeout(area: &'static str) {
+    counter!("http_request_timeouts_total", "area" => area).increment(1);
+}
+
+/// Counts a request that was rejected because too many were in flight.
+pub(crate) fn record_shed_request() {
+    counter!("http_requests_shed_total").increment(1);
+}
+
+/// Counts a request in `http_requests_in_flight` until it completes, or is dropped because the
+/// client went away.
+pub(crate) struct InFlightRequestGuard(());
+
+impl InFlightRequestGuard {
+    pub(crate) fn new() -> Self {
+        gauge!("http_requests_in_flight").increment(1);
+        InFlightRequestGuard(())
+    }
+}
+
+impl Drop for InFlightRequestGuard {
+    fn drop(&mut self) {
+        gauge!("http_requests_in_flight").decrement(1);
+    }
+}
+
+/// Counts an open WebSocket connection in `websocket_connections`, for as long as it is alive.
+pub(crate) struct WebSocketConnectionGuard(());
+
+impl WebSocketConnectionGuard {
+    pub(crate) fn new() -> Self {
+        gauge!("websocket_connections").increment(1);
+        WebSocketConnectionGuard(())
+    }
+}
+
+impl Drop for WebSocketConnectionGuard {
+    fn drop(&mut self) {
+        gauge!("websocket_connections").decrement(1);
+    }
+}
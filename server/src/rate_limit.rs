@@ -0,0 +1,437 @@
+//! Token-bucket rate limiting, as a middleware for whole routers.
+//!
+//! Every client has a bucket per [`Budget`]. Each request takes a token, and tokens are refilled
+//! at a constant rate. Clients are identified by their GitHub access token (session cookie or
+//! API token) once the [`Authenticator`] has verified it, or else by their IP address. Unverified
+//! tokens do not count, or else a client could get a fresh bucket for every request by making up
+//! tokens.
+
+use ::axum::extract::{ConnectInfo, Request, State};
+use ::axum::http::{HeaderMap, HeaderName, HeaderValue};
+use ::axum::middleware::Next;
+use ::axum::response::{IntoResponse, Response};
+use ::futures_util::future::{self, BoxFuture};
+use ::std::collections::hash_map::DefaultHasher;
+use ::std::collections::{HashMap, VecDeque};
+use ::std::hash::{Hash, Hasher};
+use ::std::net::SocketAddr;
+use ::std::sync::{Arc, Mutex, Once};
+use ::std::time::{Duration, Instant};
+use ::tracing::warn;
+
+use crate::auth::{self, Authenticator};
+use crate::route::api::ApiError;
+
+/// The in-memory store never tracks more buckets than this. Once it is full, it forgets the
+/// buckets that would be full by now, and then the oldest ones.
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+
+/// How often a full in-memory store looks for buckets that would be full by now. Each look is
+/// O(n), so it must not happen for every request.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many requests a client may send to a group of routes.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Budget {
+    /// Distinguishes the buckets of different budgets.
+    pub name: &'static str,
+    /// The number of requests that may be sent in a burst.
+    pub burst: u32,
+    /// The number of requests per minute that may be sent in the long run.
+    pub per_minute: u32,
+}
+
+impl Budget {
+    fn refill_interval(&self) -> Duration {
+        Duration::from_secs(60) / self.per_minute.max(1)
+    }
+}
+
+/// The result of taking a token from a bucket.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Decision {
+    pub allowed: bool,
+    /// Tokens left in the bucket.
+    pub remaining: u32,
+    /// Time until the bucket is full again.
+    pub reset_after: Duration,
+    /// Time until the next token becomes available, if the bucket is empty.
+    pub retry_after: Duration,
+}
+
+/// Where the buckets are kept.
+///
+/// [`InMemoryRateLimitStore`] is good enough for a single instance. Multiple instances would
+/// need a shared backend (e.g. Redis) behind this trait.
+pub(crate) trait RateLimitStore: Send + Sync {
+    fn take(&self, key: String, budget: Budget) -> BoxFuture<'_, Decision>;
+}
+
+#[derive(Default)]
+pub(crate) struct InMemoryRateLimitStore {
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Default)]
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    /// The keys of `by_key`, oldest first
+    keys: VecDeque<String>,
+    /// When a full store may look for buckets that would be full by now again
+    next_sweep: Option<Instant>,
+}
+
+struct Bucket {
+    /// Fractional tokens are not needed: the bucket is refilled one token per refill interval.
+    tokens: u32,
+    last_refill: Instant,
+    /// How long an idle bucket of its budget takes to become full
+    fills_within: Duration,
+}
+
+impl RateLimitStore for InMemoryRateLimitStore {
+    fn take(&self, key: String, budget: Budget) -> BoxFuture<'_, Decision> {
+        let decision = self
+            .buckets
+            .lock()
+            .unwrap()
+            .take(key, budget, Instant::now());
+        Box::pin(future::ready(decision))
+    }
+}
+
+impl Buckets {
+    fn take(&mut self, key: String, budget: Budget, now: Instant) -> Decision {
+        let refill_interval = budget.refill_interval();
+        if !self.by_key.contains_key(&key) {
+            self.make_room(now);
+            self.keys.push_back(key.clone());
+        }
+        let bucket = self.by_key.entry(key).or_insert(Bucket {
+            tokens: budget.burst,
+            last_refill: now,
+            fills_within: refill_interval * budget.burst.max(1),
+        });
+
+        let refills = ((now - bucket.last_refill).as_nanos() / refill_interval.as_nanos()) as u32;
+        if refills > 0 {
+            bucket.tokens = bucket.tokens.saturating_add(refills).min(budget.burst);
+            bucket.last_refill += refill_interval * refills;
+        }
+        if bucket.tokens == budget.burst {
+            bucket.last_refill = now;
+        }
+        let allowed = bucket.tokens > 0;
+        if allowed {
+            bucket.tokens -= 1;
+        }
+        let next_refill = refill_interval.saturating_sub(now - bucket.last_refill);
+        Decision {
+            allowed,
+            remaining: bucket.tokens,
+            reset_after: next_refill
+                + refill_interval * (budget.burst - bucket.tokens).saturating_sub(1),
+            retry_after: if allowed { Duration::ZERO } else { next_refill },
+        }
+    }
+
+    /// Makes room for one more bucket.
+    fn make_room(&mut self, now: Instant) {
+        if self.by_key.len() < MAX_TRACKED_BUCKETS {
+            return;
+        }
+        if self.next_sweep.is_none_or(|next_sweep| now >= next_sweep) {
+            // Buckets that would be full by now carry no information.
+            self.by_key
+                .retain(|_, bucket| now - bucket.last_refill < bucket.fills_within);
+            let by_key = &self.by_key;
+            self.keys.retain(|key| by_key.contains_key(key));
+            self.next_sweep = Some(now + SWEEP_INTERVAL);
+        }
+        while self.by_key.len() >= MAX_TRACKED_BUCKETS {
+            let Some(oldest) = self.keys.pop_front() else {
+                break;
+            };
+            self.by_key.remove(&oldest);
+        }
+    }
+}
+
+/// Applies a [`Budget`] to all requests of a router. Use it with
+/// [`axum::middleware::from_fn_with_state`] and [`enforce`].
+#[derive(Clone)]
+pub(crate) struct RateLimiter {
+    budget: Budget,
+    store: Arc<dyn RateLimitStore>,
+    /// A header set by a reverse proxy we trust, that carries the client IP address.
+    trusted_proxy_header: Option<HeaderName>,
+    /// Tells which access tokens have been verified.
+    authenticator: Authenticator,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(
+        budget: Budget,
+        store: Arc<dyn RateLimitStore>,
+        trusted_proxy_header: Option<HeaderName>,
+        authenticator: Authenticator,
+    ) -> Self {
+        Self {
+            budget,
+            store,
+            trusted_proxy_header,
+            authenticator,
+        }
+    }
+
+    /// Identifies the client of a request.
+    fn client_key(&self, request: &Request) -> String {
+        let token = auth::access_token(request.headers())
+            .filter(|token| self.authenticator.is_verified(token));
+        if let Some(token) = token {
+            // Keeps the keys short, however long the token is.
+            let mut hasher = DefaultHasher::new();
+            token.hash(&mut hasher);
+            return format!("token:{:x}", hasher.finish());
+        }
+        let proxied_ip = self.trusted_proxy_header.as_ref().and_then(|name| {
+            // Proxies append to lists like `X-Forwarded-For`. Only the last entry has been added
+            // by the proxy we trust, the others may be made up by the client.
+            request
+                .headers()
+                .get(name)?
+                .to_str()
+                .ok()?
+                .rsplit(',')
+                .next()
+                .map(|ip| ip.trim().to_string())
+        });
+        let ip = proxied_ip.or_else(|| {
+            request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+        });
+        match ip {
+            Some(ip) => format!("ip:{ip}"),
+            None => {
+                static WARNING: Once = Once::new();
+                WARNING.call_once(|| {
+                    warn!(
+                        "The client address of a request is unknown, so anonymous clients share \
+                         one rate-limit bucket. Set TRUSTED_PROXY_HEADER to the header in which \
+                         the reverse proxy passes it."
+                    )
+                });
+                "ip:unknown".to_string()
+            }
+        }
+    }
+}
+
+pub(crate) async fn enforce(
+    State(limiter): State<RateLimiter>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let budget = limiter.budget;
+    let client = limiter.client_key(&request);
+    let decision = limiter
+        .store
+        .take(format!("{}/{client}", budget.name), budget)
+        .await;
+    let mut response = if decision.allowed {
+        next.run(request).await
+    } else {
+        warn!(budget = budget.name, client, "Rate limit exceeded");
+        ApiError::RateLimited {
+            retry_after: decision.retry_after,
+        }
+        .into_response()
+    };
+    insert_headers(response.headers_mut(), budget, decision);
+    response
+}
+
+/// Adds the `RateLimit-*` headers of the IETF draft "RateLimit header fields for HTTP".
+fn insert_headers(headers: &mut HeaderMap, budget: Budget, decision: Decision) {
+    let window_seconds = (budget.refill_interval() * budget.burst).as_secs().max(1);
+    headers.insert(
+        "ratelimit-policy",
+        // Digits, `;` and `=` are always valid.
+        HeaderValue::from_str(&format!("{};w={window_seconds}", budget.burst)).unwrap(),
+    );
+    headers.insert("ratelimit-limit", HeaderValue::from(budget.burst));
+    headers.insert("ratelimit-remaining", HeaderValue::from(decision.remaining));
+    headers.insert(
+        "ratelimit-reset",
+        HeaderValue::from(ceil_seconds(decision.reset_after)),
+    );
+}
+
+/// Rounds up to whole seconds, as used by `Retry-After` and `RateLimit-Reset`.
+pub(crate) fn ceil_seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::axum::body::Body;
+    use ::axum::http::StatusCode;
+    use ::axum::routing::get;
+    use ::axum::{middleware, Router};
+
+    use super::*;
+    use crate::auth::User;
+    use crate::http_client::tests::client;
+    use crate::test_support;
+
+    /// Two requests at once, then one every 30 seconds
+    const BUDGET: Budget = Budget {
+        name: "test",
+        burst: 2,
+        per_minute: 2,
+    };
+
+    const TOKEN: &str = "ghu_verified";
+
+    fn limiter(trusted_proxy_header: Option<&'static str>) -> RateLimiter {
+        let authenticator = Authenticator::new(client("http://127.0.0.1:9/".parse().unwrap()));
+        let user = User {
+            id: 1,
+            login: "octocat".to_string(),
+        };
+        authenticator.remember(TOKEN, user);
+        RateLimiter::new(
+            BUDGET,
+            Arc::new(InMemoryRateLimitStore::default()),
+            trusted_proxy_header.map(HeaderName::from_static),
+            authenticator,
+        )
+    }
+
+    fn request(headers: &[(&str, &str)], peer: Option<&str>) -> Request {
+        let mut request = Request::builder();
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        if let Some(peer) = peer {
+            request = request.extension(ConnectInfo::<SocketAddr>(peer.parse().unwrap()));
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn buckets_refill_one_token_per_interval() {
+        let mut buckets = Buckets::default();
+        let start = Instant::now();
+        let take = |buckets: &mut Buckets, seconds| {
+            buckets.take("key".into(), BUDGET, start + Duration::from_secs(seconds))
+        };
+
+        let first = take(&mut buckets, 0);
+        assert!(first.allowed);
+        assert_eq!(first.remaining, 1);
+        assert_eq!(first.reset_after, Duration::from_secs(30));
+        let second = take(&mut buckets, 10);
+        assert!(second.allowed);
+        assert_eq!(second.remaining, 0);
+        assert_eq!(second.reset_after, Duration::from_secs(50));
+
+        let rejected = take(&mut buckets, 20);
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.retry_after, Duration::from_secs(10));
+
+        let refilled = take(&mut buckets, 30);
+        assert!(refilled.allowed);
+        assert_eq!(refilled.remaining, 0);
+        assert!(!take(&mut buckets, 40).allowed);
+
+        // A bucket that has been idle for long gets no more than its burst.
+        assert_eq!(take(&mut buckets, 600).remaining, 1);
+        assert_eq!(take(&mut buckets, 600).remaining, 0);
+        assert!(!take(&mut buckets, 600).allowed);
+    }
+
+    #[test]
+    fn the_store_never_tracks_more_than_its_maximum() {
+        let mut buckets = Buckets::default();
+        let now = Instant::now();
+        for i in 0..MAX_TRACKED_BUCKETS {
+            buckets.take(format!("client-{i}"), BUDGET, now);
+        }
+        // None of them is full again yet, so the oldest ones make room.
+        for i in 0..3 {
+            buckets.take(format!("new-client-{i}"), BUDGET, now);
+            assert_eq!(buckets.by_key.len(), MAX_TRACKED_BUCKETS);
+            assert_eq!(buckets.keys.len(), MAX_TRACKED_BUCKETS);
+            assert!(!buckets.by_key.contains_key(&format!("client-{i}")));
+        }
+        assert!(buckets.by_key.contains_key("client-3"));
+
+        // Once they would be full again, all idle buckets are forgotten at once.
+        let later = now + BUDGET.refill_interval() * BUDGET.burst;
+        buckets.take("late-client".into(), BUDGET, later);
+        assert_eq!(buckets.by_key.len(), 1);
+        assert_eq!(buckets.keys, ["late-client"]);
+    }
+
+    #[test]
+    fn clients_are_identified_by_verified_tokens_or_addresses() {
+        let limiter = limiter(Some("x-forwarded-for"));
+        let bearer = format!("Bearer {TOKEN}");
+        let key = limiter.client_key(&request(&[("authorization", &bearer)], None));
+        assert!(key.starts_with("token:"), "{key}");
+        assert!(!key.contains(TOKEN));
+        let cookie = format!("{}={TOKEN}", auth::ACCESS_TOKEN_COOKIE);
+        let cookie_key = limiter.client_key(&request(&[("cookie", &cookie)], None));
+        assert_eq!(cookie_key, key);
+
+        // Made-up tokens do not get a bucket of their own.
+        let made_up = request(
+            &[("authorization", "Bearer ghu_made_up")],
+            Some("10.0.0.1:1234"),
+        );
+        assert_eq!(limiter.client_key(&made_up), "ip:10.0.0.1");
+
+        // Only the last entry of the proxy header is trusted.
+        let proxied = request(
+            &[("x-forwarded-for", "1.2.3.4, 203.0.113.7")],
+            Some("10.0.0.1:1234"),
+        );
+        assert_eq!(limiter.client_key(&proxied), "ip:203.0.113.7");
+        assert_eq!(
+            limiter.client_key(&request(&[], Some("[::1]:1234"))),
+            "ip:::1"
+        );
+        assert_eq!(limiter.client_key(&request(&[], None)), "ip:unknown");
+
+        // Without a trusted proxy, the header may be made up by the client.
+        let limiter = self::limiter(None);
+        assert_eq!(limiter.client_key(&proxied), "ip:10.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn rejected_requests_tell_when_to_retry() {
+        let router = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(limiter(None), enforce));
+        let url = format!("http://{}/", test_support::serve(router).await);
+        let client = reqwest::Client::new();
+
+        for remaining in ["1", "0"] {
+            let response = client.get(&url).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let headers = response.headers();
+            assert_eq!(headers["ratelimit-policy"], "2;w=60");
+            assert_eq!(headers["ratelimit-limit"], "2");
+            assert_eq!(headers["ratelimit-remaining"], remaining);
+        }
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let headers = response.headers();
+        assert_eq!(headers["ratelimit-remaining"], "0");
+        assert_eq!(headers["retry-after"], "30");
+        assert_eq!(headers["ratelimit-reset"], "60");
+    }
+}
@@ -0,0 +1,516 @@
+//! Keeps tokens and secrets out of logs and error responses.
+//!
+//! Wrap secrets in [`Redacted`] (or [`SecretString`]), so that they are masked whenever they are
+//! formatted or serialized. As a second line of defense, [`ScrubbingSubscriber`] removes anything
+//! that looks like a GitHub token or a bearer credential from the fields of spans and events,
+//! before any layer (log output, OTLP export) sees them.
+
+use ::serde::{Deserialize, Serialize, Serializer};
+use ::std::any::TypeId;
+use ::std::borrow::Cow;
+use ::std::fmt;
+use ::tracing::field::{display, DisplayValue, Field, Visit};
+use ::tracing::level_filters::LevelFilter;
+use ::tracing::span::{Attributes, Id, Record};
+use ::tracing::subscriber::Interest;
+use ::tracing::{Dispatch, Event, Metadata, Subscriber, Value};
+use ::tracing_core::span::Current;
+use ::tracing_subscriber::registry::{LookupSpan, SpanData};
+
+const MASK: &str = "[redacted]";
+
+/// Prefixes of GitHub tokens: user access (`ghu_`), refresh (`ghr_`), OAuth (`gho_`),
+/// installation (`ghs_`), personal access (`ghp_`, `github_pat_`).
+const TOKEN_PREFIXES: &[&str] = &["ghu_", "ghr_", "gho_", "ghs_", "ghp_", "github_pat_"];
+
+/// The scheme of bearer credentials, which is case-insensitive like all HTTP authentication
+/// schemes
+const BEARER_SCHEME: &str = "bearer ";
+
+/// The most fields a callsite of the `tracing` macros has in practice. The values of spans and
+/// events with more fields are not scrubbed.
+const MAX_FIELDS: usize = 32;
+
+/// A value that must never be shown. `Debug`, `Display` and `Serialize` all print a mask instead.
+/// Use [`Redacted::expose`] where the real value is needed.
+#[derive(Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub(crate) struct Redacted<T>(T);
+
+pub(crate) type SecretString = Redacted<String>;
+
+impl<T> Redacted<T> {
+    pub(crate) fn new(value: T) -> Self {
+        Redacted(value)
+    }
+
+    pub(crate) fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> From<T> for Redacted<T> {
+    fn from(value: T) -> Self {
+        Redacted(value)
+    }
+}
+
+impl<T> fmt::Debug for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(MASK)
+    }
+}
+
+impl<T> fmt::Display for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(MASK)
+    }
+}
+
+impl<T> Serialize for Redacted<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(MASK)
+    }
+}
+
+/// Replaces GitHub tokens and bearer credentials in `text` with a mask. The token prefix (or
+/// the authentication scheme) is kept, because it tells what kind of token it was.
+pub(crate) fn scrub(text: &str) -> Cow<'_, str> {
+    let Some(mut found) = find_first_prefix(text) else {
+        return Cow::Borrowed(text);
+    };
+    let mut scrubbed = String::with_capacity(text.len());
+    let mut rest = text;
+    loop {
+        let (start, prefix_len) = found;
+        let (before, from_prefix) = rest.split_at(start);
+        let (prefix, after_prefix) = from_prefix.split_at(prefix_len);
+        let secret_len = after_prefix
+            .find(|c: char| !is_token_char(c))
+            .unwrap_or(after_prefix.len());
+        scrubbed.push_str(before);
+        scrubbed.push_str(prefix);
+        if secret_len > 0 {
+            scrubbed.push_str(MASK);
+        }
+        rest = &after_prefix[secret_len..];
+        match find_first_prefix(rest) {
+            Some(next) => found = next,
+            None => break,
+        }
+    }
+    scrubbed.push_str(rest);
+    Cow::Owned(scrubbed)
+}
+
+/// Returns the start and the length of the first token prefix or bearer scheme in `text`.
+fn find_first_prefix(text: &str) -> Option<(usize, usize)> {
+    let token = TOKEN_PREFIXES
+        .iter()
+        .filter_map(|prefix| {
+            text.match_indices(prefix)
+                // A token prefix must start a word: `xghu_…` is no token.
+                .find(|(start, _)| !text[..*start].ends_with(|c: char| c.is_ascii_alphanumeric()))
+                .map(|(start, _)| (start, prefix.len()))
+        })
+        .min_by_key(|(start, _)| *start);
+    let bearer = text
+        .as_bytes()
+        .windows(BEARER_SCHEME.len())
+        .position(|window| window.eq_ignore_ascii_case(BEARER_SCHEME.as_bytes()))
+        .map(|start| {
+            // Any further spaces before the credentials belong to the prefix.
+            let spaces = text[start + BEARER_SCHEME.len()..]
+                .bytes()
+                .take_while(|&b| b == b' ')
+                .count();
+            (start, BEARER_SCHEME.len() + spaces)
+        });
+    match (token, bearer) {
+        (Some(token), Some(bearer)) => Some(if bearer.0 < token.0 { bearer } else { token }),
+        (token, bearer) => token.or(bearer),
+    }
+}
+
+/// Characters of tokens: GitHub tokens are alphanumeric with underscores, bearer credentials may
+/// also be base64 or JWTs.
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '~' | '+' | '/' | '=')
+}
+
+/// Wraps the subscriber that the layers are stacked on, and [`scrub`]s the values of span and
+/// event fields before passing them on. Spans and events without anything to scrub are passed on
+/// unchanged.
+pub(crate) struct ScrubbingSubscriber<S>(pub S);
+
+impl<S> Subscriber for ScrubbingSubscriber<S>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_register_dispatch(&self, subscriber: &Dispatch) {
+        self.0.on_register_dispatch(subscriber)
+    }
+
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.0.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.0.max_level_hint()
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let scrubbed = ScrubbedValues::of(|visitor| span.record(visitor));
+        let Some(entries) = scrubbed.entries() else {
+            return self.0.new_span(span);
+        };
+        let metadata = span.metadata();
+        let values = metadata.fields().value_set(&entries);
+        let span = match span.parent() {
+            _ if span.is_root() => Attributes::new_root(metadata, &values),
+            Some(parent) => Attributes::child_of(parent.clone(), metadata, &values),
+            None => Attributes::new(metadata, &values),
+        };
+        self.0.new_span(&span)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let scrubbed = ScrubbedValues::of(|visitor| values.record(visitor));
+        let (Some(entries), Some(data)) = (scrubbed.entries(), self.0.span_data(span)) else {
+            return self.0.record(span, values);
+        };
+        let values = data.metadata().fields().value_set(&entries);
+        self.0.record(span, &Record::new(&values))
+    }
+
+    fn record_follows_from(&self, span: &Id, follows: &Id) {
+        self.0.record_follows_from(span, follows)
+    }
+
+    fn event_enabled(&self, event: &Event<'_>) -> bool {
+        self.0.event_enabled(event)
+    }
+
+    fn event(&self, event: &Event<'_>) {
+        let scrubbed = ScrubbedValues::of(|visitor| event.record(visitor));
+        let Some(entries) = scrubbed.entries() else {
+            return self.0.event(event);
+        };
+        let metadata = event.metadata();
+        let values = metadata.fields().value_set(&entries);
+        let event = match event.parent() {
+            _ if event.is_root() => Event::new_child_of(None, metadata, &values),
+            Some(parent) => Event::new_child_of(parent.clone(), metadata, &values),
+            None => Event::new(metadata, &values),
+        };
+        self.0.event(&event)
+    }
+
+    fn enter(&self, span: &Id) {
+        self.0.enter(span)
+    }
+
+    fn exit(&self, span: &Id) {
+        self.0.exit(span)
+    }
+
+    fn clone_span(&self, id: &Id) -> Id {
+        self.0.clone_span(id)
+    }
+
+    fn try_close(&self, id: Id) -> bool {
+        self.0.try_close(id)
+    }
+
+    fn current_span(&self) -> Current {
+        self.0.current_span()
+    }
+
+    // Lets the layers be found, e.g. by `tracing_opentelemetry::OpenTelemetrySpanExt`.
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            Some(self as *const Self as *const ())
+        } else {
+            self.0.downcast_raw(id)
+        }
+    }
+}
+
+/// The values of the fields of a span or event, with the text ones [`scrub`]bed.
+#[derive(Default)]
+struct ScrubbedValues {
+    values: Vec<(Field, ScrubbedValue)>,
+    /// Whether scrubbing has changed any value
+    is_changed: bool,
+}
+
+/// Keeps the type of numbers and booleans, so that layers record them as before.
+enum ScrubbedValue {
+    Bool(bool),
+    F64(f64),
+    I64(i64),
+    U64(u64),
+    I128(i128),
+    U128(u128),
+    Str(String),
+    /// Formatted with `Debug`, or an error formatted with `Display`
+    Formatted(DisplayValue<String>),
+}
+
+impl ScrubbedValues {
+    fn of(record: impl FnOnce(&mut dyn Visit)) -> Self {
+        let mut values = ScrubbedValues::default();
+        record(&mut values);
+        values
+    }
+
+    /// The entries of a `ValueSet` with the scrubbed values, if scrubbing has changed anything.
+    /// Entries beyond the values are left empty.
+    fn entries(&self) -> Option<[(&Field, Option<&dyn Value>); MAX_FIELDS]> {
+        let (first_field, _) = self.values.first()?;
+        if !self.is_changed || self.values.len() > MAX_FIELDS {
+            return None;
+        }
+        let mut entries = [(first_field, None); MAX_FIELDS];
+        for (entry, (field, value)) in entries.iter_mut().zip(&self.values) {
+            *entry = (field, Some(value.as_value()));
+        }
+        Some(entries)
+    }
+
+    fn push_text(&mut self, field: &Field, text: String, to_value: fn(String) -> ScrubbedValue) {
+        let text = match scrub(&text) {
+            Cow::Borrowed(_) => text,
+            Cow::Owned(scrubbed) => {
+                self.is_changed = true;
+                scrubbed
+            }
+        };
+        self.values.push((field.clone(), to_value(text)));
+    }
+}
+
+impl ScrubbedValue {
+    fn as_value(&self) -> &dyn Value {
+        match self {
+            ScrubbedValue::Bool(value) => value,
+            ScrubbedValue::F64(value) => value,
+            ScrubbedValue::I64(value) => value,
+            ScrubbedValue::U64(value) => value,
+            ScrubbedValue::I128(value) => value,
+            ScrubbedValue::U128(value) => value,
+            ScrubbedValue::Str(value) => value,
+            ScrubbedValue::Formatted(value) => value,
+        }
+    }
+}
+
+impl Visit for ScrubbedValues {
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.values
+            .push((field.clone(), ScrubbedValue::Bool(value)));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.values.push((field.clone(), ScrubbedValue::F64(value)));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.values.push((field.clone(), ScrubbedValue::I64(value)));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.values.push((field.clone(), ScrubbedValue::U64(value)));
+    }
+
+    fn record_i128(&mut self, field: &Field, value: i128) {
+        self.values
+            .push((field.clone(), ScrubbedValue::I128(value)));
+    }
+
+    fn record_u128(&mut self, field: &Field, value: u128) {
+        self.values
+            .push((field.clone(), ScrubbedValue::U128(value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push_text(field, value.to_string(), ScrubbedValue::Str);
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.push_text(field, value.to_string(), |text| {
+            ScrubbedValue::Formatted(display(text))
+        });
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.push_text(field, format!("{value:?}"), |text| {
+            ScrubbedValue::Formatted(display(text))
+        });
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use ::std::io;
+    use ::std::sync::{Arc, Mutex};
+    use ::tracing::{info, info_span, warn};
+    use ::tracing_subscriber::layer::{Context, SubscriberExt};
+    use ::tracing_subscriber::Layer;
+
+    use super::*;
+
+    /// Captures what the layers of a [`ScrubbingSubscriber`] get: the output of a `fmt` layer, and
+    /// the raw field values, which is what an exporting layer would see.
+    #[derive(Clone, Default)]
+    pub(crate) struct CapturedLogs(Arc<Mutex<String>>);
+
+    impl CapturedLogs {
+        /// Install it with `tracing::subscriber::set_default`.
+        pub(crate) fn subscriber(&self) -> impl Subscriber + Send + Sync {
+            let writer = self.clone();
+            ScrubbingSubscriber(
+                ::tracing_subscriber::registry()
+                    .with(
+                        ::tracing_subscriber::fmt::layer()
+                            .with_ansi(false)
+                            .with_writer(move || writer.clone()),
+                    )
+                    .with(FieldLayer(self.clone())),
+            )
+        }
+
+        pub(crate) fn text(&self) -> String {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0
+                .lock()
+                .unwrap()
+                .push_str(&String::from_utf8_lossy(buf));
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct FieldLayer(CapturedLogs);
+
+    impl Visit for FieldLayer {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            let line = format!("field {}={value:?}\n", field.name());
+            self.0 .0.lock().unwrap().push_str(&line);
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for FieldLayer {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+            attrs.record(&mut FieldLayer(self.0.clone()));
+        }
+
+        fn on_record(&self, _: &Id, values: &Record<'_>, _: Context<'_, S>) {
+            values.record(&mut FieldLayer(self.0.clone()));
+        }
+
+        fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+            event.record(&mut FieldLayer(self.0.clone()));
+        }
+    }
+
+    #[derive(Serialize)]
+    struct Credentials {
+        user: &'static str,
+        token: SecretString,
+    }
+
+    #[test]
+    fn redacted_values_are_never_shown() {
+        let secret = SecretString::new("ghu_secret".to_string());
+        assert_eq!(format!("{secret:?}"), "[redacted]");
+        assert_eq!(format!("{secret}"), "[redacted]");
+        assert_eq!(secret.expose(), "ghu_secret");
+        let credentials = Credentials {
+            user: "octocat",
+            token: secret,
+        };
+        assert_eq!(
+            serde_json::to_string(&credentials).unwrap(),
+            r#"{"user":"octocat","token":"[redacted]"}"#
+        );
+        let number = Redacted::new(42);
+        assert_eq!(format!("{number:?}"), "[redacted]");
+    }
+
+    #[test]
+    fn scrubs_github_tokens() {
+        assert_eq!(
+            scrub("access_token=ghu_abc123&refresh_token=ghr_def456&scope="),
+            "access_token=ghu_[redacted]&refresh_token=ghr_[redacted]&scope="
+        );
+        assert_eq!(
+            scrub(r#"{"token":"github_pat_11AB_cd"}"#),
+            r#"{"token":"github_pat_[redacted]"}"#
+        );
+        assert!(matches!(scrub("no secrets here"), Cow::Borrowed(_)));
+        // Not at the start of a word
+        assert_eq!(scrub("xghu_abc"), "xghu_abc");
+    }
+
+    #[test]
+    fn scrubs_bearer_credentials_in_any_case() {
+        assert_eq!(
+            scrub("Authorization: Bearer abc.def-ghi"),
+            "Authorization: Bearer [redacted]"
+        );
+        assert_eq!(
+            scrub("authorization: bearer abc"),
+            "authorization: bearer [redacted]"
+        );
+        assert_eq!(scrub("BEARER  abc, done"), "BEARER  [redacted], done");
+    }
+
+    #[test]
+    fn scrubs_the_fields_that_all_layers_see() {
+        let logs = CapturedLogs::default();
+        ::tracing::subscriber::with_default(logs.subscriber(), || {
+            let span = info_span!(
+                "request",
+                token = "ghu_span",
+                status = ::tracing::field::Empty
+            );
+            let _entered = span.enter();
+            span.record("status", 200);
+            span.record("token", "ghr_recorded");
+            info!(header = ?"bearer ghu_debug", count = 3, "Signed in with ghu_message");
+            let error = io::Error::other("rejected Bearer ghp_error");
+            warn!(error = &error as &dyn std::error::Error, "Failed");
+        });
+        let text = logs.text();
+        for secret in [
+            "ghu_span",
+            "ghr_recorded",
+            "ghu_debug",
+            "ghu_message",
+            "ghp_error",
+        ] {
+            assert!(!text.contains(secret), "{secret} in {text}");
+        }
+        assert!(
+            text.contains("field message=Signed in with ghu_[redacted]\n"),
+            "{text}"
+        );
+        assert!(text.contains("field token=\"ghr_[redacted]\"\n"), "{text}");
+        // Other values keep their type.
+        assert!(text.contains("field count=3\n"), "{text}");
+        assert!(text.contains("field status=200\n"), "{text}");
+    }
+}
@@ -1,6 +1,8 @@
 use ::axum::{extract::OriginalUri, http::StatusCode};
 
+pub(crate) mod admin;
 pub(crate) mod api;
+pub(crate) mod health;
 pub(crate) mod oauth;
 pub(crate) mod spa;
 
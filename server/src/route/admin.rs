@@ -0,0 +1,128 @@
+//! Endpoints for operators. All of them require the `ADMIN_BEARER_TOKEN` secret in an
+//! `Authorization: Bearer` header. Without that secret, `/admin` only returns `404`.
+//!
+//! `/admin/log-level` reads (`GET`), replaces (`PUT`) and restores (`DELETE`) the directives of
+//! the log filter, in the syntax of `RUST_LOG`:
+//!
+//! ```sh
+//! curl -X PUT -H "Authorization: Bearer $ADMIN_BEARER_TOKEN" -H 'Content-Type: application/json' \
+//!   -d '{"directives": "server=trace,tower_http=debug", "ttlSeconds": 600}' \
+//!   http://localhost:8000/admin/log-level
+//! ```
+//!
+//! With `ttlSeconds`, the initial directives are restored after that time. This keeps a
+//! forgotten `trace` level from flooding the logs.
+//!
+//! `POST /admin/config/reload` loads the configuration from its sources again (see
+//! `crate::config`) and returns the effective settings, with secrets masked. An invalid
+//! configuration is rejected with `422`, and the current one stays in place.
+
+use ::axum::extract::rejection::JsonRejection;
+use ::axum::extract::{FromRef, Request, State};
+use ::axum::http::{header, StatusCode};
+use ::axum::middleware::{self, Next};
+use ::axum::response::{IntoResponse, Response};
+use ::axum::routing::{any, get, post};
+use ::axum::{Json, Router};
+use ::common::tracing::{LogFilter, LogFilterStatus};
+use ::serde::Deserialize;
+use ::std::time::Duration;
+use ::tracing_subscriber::EnvFilter;
+
+use super::api::ApiError;
+use crate::auth;
+use crate::config::SharedConfig;
+
+#[derive(Clone)]
+struct AdminState {
+    log_filter: LogFilter,
+    config: SharedConfig,
+}
+
+impl FromRef<AdminState> for LogFilter {
+    fn from_ref(state: &AdminState) -> Self {
+        state.log_filter.clone()
+    }
+}
+
+impl FromRef<AdminState> for SharedConfig {
+    fn from_ref(state: &AdminState) -> Self {
+        state.config.clone()
+    }
+}
+
+pub(crate) fn router(log_filter: LogFilter, config: SharedConfig) -> Router<()> {
+    Router::new()
+        .route(
+            "/log-level",
+            get(get_log_level)
+                .put(put_log_level)
+                .delete(delete_log_level),
+        )
+        .route("/config/reload", post(reload_config))
+        .route("/*_", any(super::no_route))
+        .route("/", any(super::no_route))
+        .layer(middleware::from_fn_with_state(
+            config.clone(),
+            require_bearer_token,
+        ))
+        .with_state(AdminState { log_filter, config })
+}
+
+/// The token is read for every request, so that a reload can rotate it.
+async fn require_bearer_token(
+    State(config): State<SharedConfig>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(bearer_token) = config.current().admin_bearer_token.clone() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if auth::has_bearer_token(request.headers(), &bearer_token) {
+        next.run(request).await
+    } else {
+        (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct LogLevelRequest {
+    directives: String,
+    ttl_seconds: Option<u64>,
+}
+
+async fn get_log_level(
+    State(log_filter): State<LogFilter>,
+) -> Result<Json<LogFilterStatus>, ApiError> {
+    Ok(Json(log_filter.status()?))
+}
+
+async fn put_log_level(
+    State(log_filter): State<LogFilter>,
+    request: Result<Json<LogLevelRequest>, JsonRejection>,
+) -> Result<Json<LogFilterStatus>, ApiError> {
+    let Json(request) = request?;
+    let filter = EnvFilter::try_new(&request.directives)
+        .map_err(|e| ApiError::InvalidRequest(format!("Invalid directives: {e}")))?;
+    log_filter.set(filter, request.ttl_seconds.map(Duration::from_secs))?;
+    Ok(Json(log_filter.status()?))
+}
+
+async fn delete_log_level(
+    State(log_filter): State<LogFilter>,
+) -> Result<Json<LogFilterStatus>, ApiError> {
+    log_filter.revert();
+    Ok(Json(log_filter.status()?))
+}
+
+async fn reload_config(State(config): State<SharedConfig>) -> Result<String, ApiError> {
+    let settings = config
+        .reload()
+        .map_err(|e| ApiError::InvalidRequest(format!("{e:#}")))?;
+    Ok(settings.to_string())
+}
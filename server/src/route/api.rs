@@ -1,11 +1,102 @@
+use ::axum::extract::FromRef;
+use ::axum::middleware;
 use ::axum::routing::any;
 use ::axum::{routing::get, Router};
+use ::std::sync::Arc;
 
-pub(crate) fn router() -> Router<()> {
+use crate::auth::Authenticator;
+use crate::broker::Broker;
+use crate::csrf;
+use crate::rate_limit::RateLimiter;
+use crate::shutdown::Shutdown;
+
+pub(crate) use self::error::ApiError;
+pub(crate) use self::preferences::{InMemoryPreferencesStore, PreferencesStore};
+
+mod client_logs;
+mod csp_report;
+mod error;
+mod events;
+mod preferences;
+mod ws;
+
+/// Shared state of all API handlers.
+#[derive(Clone)]
+pub(crate) struct ApiState {
+    pub authenticator: Authenticator,
+    pub preferences: Arc<dyn PreferencesStore>,
+    pub broker: Broker,
+    /// The budget of `/api/client-logs`, on top of the budget of the whole API.
+    pub client_logs_limiter: RateLimiter,
+    /// Ends WebSocket sessions and SSE streams
+    pub shutdown: Shutdown,
+}
+
+#[cfg(test)]
+impl ApiState {
+    /// A state in which `authenticator` identifies users, for tests. `/api/client-logs` has a
+    /// budget that tests do not use up.
+    pub(crate) fn for_tests(authenticator: Authenticator) -> Self {
+        use crate::rate_limit::{Budget, InMemoryRateLimitStore};
+
+        let budget = Budget {
+            name: "client-logs",
+            burst: 100,
+            per_minute: 100,
+        };
+        let store = Arc::new(InMemoryRateLimitStore::default());
+        ApiState {
+            client_logs_limiter: RateLimiter::new(budget, store, None, authenticator.clone()),
+            authenticator,
+            preferences: Arc::new(InMemoryPreferencesStore::default()),
+            broker: Broker::default(),
+            shutdown: Shutdown::new(),
+        }
+    }
+}
+
+impl FromRef<ApiState> for Authenticator {
+    fn from_ref(state: &ApiState) -> Self {
+        state.authenticator.clone()
+    }
+}
+
+impl FromRef<ApiState> for Arc<dyn PreferencesStore> {
+    fn from_ref(state: &ApiState) -> Self {
+        state.preferences.clone()
+    }
+}
+
+impl FromRef<ApiState> for Broker {
+    fn from_ref(state: &ApiState) -> Self {
+        state.broker.clone()
+    }
+}
+
+impl FromRef<ApiState> for Shutdown {
+    fn from_ref(state: &ApiState) -> Self {
+        state.shutdown.clone()
+    }
+}
+
+pub(crate) fn router(state: ApiState) -> Router<()> {
     Router::new()
         .route("/greet", get(greet))
+        .route("/csrf-token", get(csrf::get_token))
+        .nest("/me/preferences", preferences::router())
+        .nest(
+            "/client-logs",
+            client_logs::router(state.client_logs_limiter.clone()),
+        )
+        .route("/ws", get(ws::upgrade))
+        .route("/events", get(events::stream))
         .route("/*_", any(super::no_route))
         .route("/", any(super::no_route))
+        .layer(middleware::from_fn(csrf::protect))
+        // Added after the CSRF protection, which it is exempt from: browsers send reports without
+        // a token, and they change nothing.
+        .nest("/csp-report", csp_report::router())
+        .with_state(state)
 }
 
 async fn greet() -> &'static str {
@@ -0,0 +1,374 @@
+//! Log events from the browser, so that errors in the UI (failed requests, decode failures, …)
+//! show up in the server's log.
+//!
+//! The UI sends batches to `POST /api/client-logs`:
+//!
+//! ```json
+//! {
+//!   "events": [
+//!     {
+//!       "level": "error",
+//!       "message": "Cannot decode the GitHub user: …",
+//!       "page": "/sign-in",
+//!       "userAgent": "Mozilla/5.0 …",
+//!       "requestId": "1c5c0a5e-…"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! `requestId` is the `X-Request-Id` of the server response the event relates to, if any. Every
+//! event is logged with the target `client`, so `RUST_LOG=client=warn` selects them. What the
+//! client sends is logged as fields, which are escaped: a newline in a message cannot start a
+//! line that looks like a log event of the server.
+
+use ::axum::extract::rejection::JsonRejection;
+use ::axum::extract::{DefaultBodyLimit, State};
+use ::axum::http::{HeaderMap, StatusCode};
+use ::axum::middleware;
+use ::axum::routing::post;
+use ::axum::{Json, Router};
+use ::serde::Deserialize;
+use ::tracing::{debug, error, info, warn, Span};
+
+use super::{ApiError, ApiState};
+use crate::auth::{self, Authenticator};
+use crate::rate_limit::{self, RateLimiter};
+
+/// Larger batches are rejected without being parsed.
+const MAX_BATCH_BYTES: usize = 32 * 1024;
+
+const MAX_EVENTS_PER_BATCH: usize = 50;
+
+const MAX_MESSAGE_CHARS: usize = 2000;
+
+/// For `page`, `userAgent` and `requestId`.
+const MAX_FIELD_CHARS: usize = 500;
+
+/// `limiter` keeps a broken UI from flooding the log. It applies per client, in addition to the
+/// budget of the whole API.
+pub(super) fn router(limiter: RateLimiter) -> Router<ApiState> {
+    Router::new()
+        .route("/", post(post_client_logs))
+        .layer(DefaultBodyLimit::max(MAX_BATCH_BYTES))
+        .layer(middleware::from_fn_with_state(limiter, rate_limit::enforce))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClientLogBatch {
+    events: Vec<ClientLogEvent>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct ClientLogEvent {
+    level: ClientLogLevel,
+    message: String,
+    /// The path of the page that was shown, e.g. `/sign-in`.
+    page: String,
+    user_agent: String,
+    #[serde(default)]
+    request_id: Option<String>,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum ClientLogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl ClientLogEvent {
+    fn validate(&self) -> Result<(), String> {
+        if self.message.chars().count() > MAX_MESSAGE_CHARS {
+            return Err(format!(
+                "`message` must not be longer than {MAX_MESSAGE_CHARS} characters"
+            ));
+        }
+        for (name, value) in [
+            ("page", Some(&self.page)),
+            ("userAgent", Some(&self.user_agent)),
+            ("requestId", self.request_id.as_ref()),
+        ] {
+            if value.is_some_and(|value| value.chars().count() > MAX_FIELD_CHARS) {
+                return Err(format!(
+                    "`{name}` must not be longer than {MAX_FIELD_CHARS} characters"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Re-emits the event into the server's log.
+    fn emit(&self, user_id: Option<u64>) {
+        let ClientLogEvent {
+            level,
+            message,
+            page,
+            user_agent,
+            request_id,
+        } = self;
+        // The level of a `tracing` event must be known at compile time.
+        macro_rules! emit_at {
+            ($macro:ident) => {
+                $macro!(
+                    target: "client",
+                    client_message = message.as_str(),
+                    page = page.as_str(),
+                    user_agent = user_agent.as_str(),
+                    client_request_id = request_id.as_deref(),
+                    user_id,
+                    "Reported by the UI"
+                )
+            };
+        }
+        match level {
+            ClientLogLevel::Error => emit_at!(error),
+            ClientLogLevel::Warn => emit_at!(warn),
+            ClientLogLevel::Info => emit_at!(info),
+            ClientLogLevel::Debug => emit_at!(debug),
+        }
+    }
+}
+
+/// Anonymous clients may report, too: errors during sign-in are the most interesting ones. The
+/// user is only named if the API has verified their token recently: a made-up token must not make
+/// the server call GitHub.
+async fn post_client_logs(
+    State(authenticator): State<Authenticator>,
+    headers: HeaderMap,
+    batch: Result<Json<ClientLogBatch>, JsonRejection>,
+) -> Result<StatusCode, ApiError> {
+    let Json(batch) = batch?;
+    if batch.events.len() > MAX_EVENTS_PER_BATCH {
+        return Err(ApiError::PayloadTooLarge(format!(
+            "Send at most {MAX_EVENTS_PER_BATCH} events per batch"
+        )));
+    }
+    for (index, event) in batch.events.iter().enumerate() {
+        event
+            .validate()
+            .map_err(|e| ApiError::InvalidRequest(format!("events[{index}]: {e}")))?;
+    }
+    let user_id = auth::access_token(&headers)
+        .and_then(|token| authenticator.verified_user(token))
+        .map(|user| user.id);
+    if let Some(user_id) = user_id {
+        Span::current().record("user_id", user_id);
+    }
+    for event in &batch.events {
+        event.emit(user_id);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::axum::http::HeaderName;
+    use ::axum::routing::get;
+    use ::reqwest::Url;
+    use ::serde_json::{json, Value};
+    use ::std::sync::atomic::{AtomicU32, Ordering};
+    use ::std::sync::Arc;
+
+    use super::*;
+    use crate::auth::{User, ACCESS_TOKEN_COOKIE};
+    use crate::http_client;
+    use crate::rate_limit::{Budget, InMemoryRateLimitStore};
+    use crate::redact::tests::CapturedLogs;
+    use crate::test_support;
+
+    const VERIFIED_TOKEN: &str = "gho_verified";
+
+    /// The endpoint, with a GitHub whose `/user` requests are counted
+    struct Endpoint {
+        url: String,
+        github_requests: Arc<AtomicU32>,
+    }
+
+    /// Serves the endpoint, which lets each client send `burst` batches.
+    async fn serve(burst: u32) -> Endpoint {
+        let github_requests = Arc::new(AtomicU32::new(0));
+        let github = Router::new().route(
+            "/user",
+            get({
+                let github_requests = github_requests.clone();
+                move || async move {
+                    github_requests.fetch_add(1, Ordering::SeqCst);
+                    Json(json!({ "id": 2, "login": "mallory" }))
+                }
+            }),
+        );
+        let github_url = format!("http://{}", test_support::serve(github).await);
+        let authenticator =
+            Authenticator::new(http_client::tests::client(Url::parse(&github_url).unwrap()));
+        let user = User {
+            id: 1,
+            login: "octocat".to_string(),
+        };
+        authenticator.remember(VERIFIED_TOKEN, user);
+        let budget = Budget {
+            name: "client-logs",
+            burst,
+            per_minute: 1,
+        };
+        let limiter = RateLimiter::new(
+            budget,
+            Arc::new(InMemoryRateLimitStore::default()),
+            None,
+            authenticator.clone(),
+        );
+        let state = ApiState {
+            client_logs_limiter: limiter.clone(),
+            ..ApiState::for_tests(authenticator)
+        };
+        let router = Router::new()
+            .nest("/client-logs", router(limiter))
+            .with_state(state);
+        Endpoint {
+            url: format!("http://{}/client-logs", test_support::serve(router).await),
+            github_requests,
+        }
+    }
+
+    fn event(level: &str, message: &str) -> Value {
+        json!({
+            "level": level,
+            "message": message,
+            "page": "/sign-in",
+            "userAgent": "Mozilla/5.0",
+        })
+    }
+
+    async fn post(url: &str, events: Vec<Value>, headers: &[(HeaderName, &str)]) -> StatusCode {
+        let mut request = reqwest::Client::new()
+            .post(url)
+            .json(&json!({ "events": events }));
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        request.send().await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn events_are_logged_at_their_level_with_escaped_fields() {
+        let logs = CapturedLogs::default();
+        let _logging = ::tracing::subscriber::set_default(logs.subscriber());
+        let endpoint = serve(10).await;
+        let events = vec![
+            event("error", "Cannot decode the GitHub user"),
+            event("warn", "Slow"),
+            event("info", "Signed in"),
+            event("debug", "Rendered"),
+        ];
+        assert_eq!(
+            post(&endpoint.url, events, &[]).await,
+            StatusCode::NO_CONTENT
+        );
+        let forged = event("info", "Ok\n2024-01-01T00:00:00Z ERROR server: Forged");
+        assert_eq!(
+            post(&endpoint.url, vec![forged], &[]).await,
+            StatusCode::NO_CONTENT
+        );
+
+        let text = logs.text();
+        let lines: Vec<_> = text
+            .lines()
+            .filter(|line| line.contains(" client: "))
+            .collect();
+        for (level, message) in [
+            ("ERROR", "Cannot decode the GitHub user"),
+            ("WARN", "Slow"),
+            ("INFO", "Signed in"),
+            ("DEBUG", "Rendered"),
+        ] {
+            let expected = format!("client_message=\"{message}\" page=\"/sign-in\"");
+            assert!(
+                lines
+                    .iter()
+                    .any(|line| line.contains(level) && line.contains(&expected)),
+                "no {level} {message} in {text}"
+            );
+        }
+        assert!(
+            text.contains(r#"client_message="Ok\n2024-01-01T00:00:00Z ERROR server: Forged""#),
+            "{text}"
+        );
+        assert!(!text.lines().any(|line| line.starts_with("2024")), "{text}");
+    }
+
+    #[tokio::test]
+    async fn oversized_batches_are_rejected() {
+        let endpoint = serve(10).await;
+        let events = vec![event("info", "Hello"); MAX_EVENTS_PER_BATCH + 1];
+        assert_eq!(
+            post(&endpoint.url, events, &[]).await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        let long_message = "a".repeat(MAX_MESSAGE_CHARS + 1);
+        assert_eq!(
+            post(&endpoint.url, vec![event("info", &long_message)], &[]).await,
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        // Larger than the body limit, although each event is valid
+        let events = vec![event("info", &"a".repeat(MAX_MESSAGE_CHARS)); 20];
+        assert_eq!(
+            post(&endpoint.url, events, &[]).await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            post(&endpoint.url, vec![event("fatal", "Hello")], &[]).await,
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+    }
+
+    #[tokio::test]
+    async fn only_verified_tokens_name_the_user() {
+        let logs = CapturedLogs::default();
+        let _logging = ::tracing::subscriber::set_default(logs.subscriber());
+        let endpoint = serve(10).await;
+
+        let cookie = format!("{ACCESS_TOKEN_COOKIE}=gho_made_up");
+        let headers = [(::axum::http::header::COOKIE, cookie.as_str())];
+        let status = post(&endpoint.url, vec![event("info", "Anonymous")], &headers).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(endpoint.github_requests.load(Ordering::SeqCst), 0);
+
+        let authorization = format!("Bearer {VERIFIED_TOKEN}");
+        let headers = [(::axum::http::header::AUTHORIZATION, authorization.as_str())];
+        let status = post(&endpoint.url, vec![event("info", "Named")], &headers).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let text = logs.text();
+        let line = |message: &str| {
+            text.lines()
+                .filter(|line| line.contains(" client: "))
+                .find(|line| line.contains(&format!("client_message=\"{message}\"")))
+                .unwrap_or_else(|| panic!("no {message} in {text}"))
+        };
+        assert!(!line("Anonymous").contains("user_id"), "{text}");
+        assert!(line("Named").contains("user_id=1"), "{text}");
+    }
+
+    #[tokio::test]
+    async fn each_client_has_its_own_budget() {
+        let endpoint = serve(2).await;
+        let authorization = format!("Bearer {VERIFIED_TOKEN}");
+        let verified = [(::axum::http::header::AUTHORIZATION, authorization.as_str())];
+        for _ in 0..2 {
+            let status = post(&endpoint.url, vec![event("info", "Hello")], &verified).await;
+            assert_eq!(status, StatusCode::NO_CONTENT);
+        }
+        let status = post(&endpoint.url, vec![event("info", "Hello")], &verified).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+        // Anonymous clients are identified by their address.
+        assert_eq!(
+            post(&endpoint.url, vec![event("info", "Hello")], &[]).await,
+            StatusCode::NO_CONTENT
+        );
+    }
+}
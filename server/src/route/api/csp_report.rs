@@ -0,0 +1,65 @@
+//! Collects reports of `Content-Security-Policy` violations (see `crate::security`), and logs
+//! them.
+//!
+//! Browsers send either a single report as `application/csp-report` (for `report-uri`), or a list
+//! of reports as `application/reports+json` (for `report-to`). Neither is `application/json`, so
+//! the body is parsed by hand.
+
+use ::axum::body::Bytes;
+use ::axum::extract::DefaultBodyLimit;
+use ::axum::http::StatusCode;
+use ::axum::routing::post;
+use ::axum::Router;
+use ::serde_json::Value;
+use ::tracing::warn;
+
+use super::{ApiError, ApiState};
+
+/// Reports carry a sample of the violating code at most. Anything bigger is no report.
+const MAX_REPORT_BYTES: usize = 16 * 1024;
+
+pub(super) fn router() -> Router<ApiState> {
+    Router::new()
+        .route("/", post(collect))
+        .layer(DefaultBodyLimit::max(MAX_REPORT_BYTES))
+}
+
+async fn collect(body: Bytes) -> Result<StatusCode, ApiError> {
+    let reports = match serde_json::from_slice::<Value>(&body) {
+        // `report-uri`: `{"csp-report": {"document-uri": …, "violated-directive": …, …}}`
+        Ok(Value::Object(mut report)) => match report.remove("csp-report") {
+            Some(report) => vec![report],
+            None => Vec::new(),
+        },
+        // `report-to`: `[{"type": "csp-violation", "body": {"documentURL": …, …}}, …]`
+        Ok(Value::Array(reports)) => reports
+            .into_iter()
+            .filter(|report| report["type"] == "csp-violation")
+            .map(|mut report| report["body"].take())
+            .collect(),
+        _ => Vec::new(),
+    };
+    if reports.is_empty() {
+        return Err(ApiError::InvalidRequest(
+            "Expected a CSP violation report".to_string(),
+        ));
+    }
+    for report in reports {
+        let field = |legacy_name: &str, name: &str| {
+            report
+                .get(legacy_name)
+                .or_else(|| report.get(name))
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        warn!(
+            document = field("document-uri", "documentURL"),
+            directive = field("effective-directive", "effectiveDirective"),
+            blocked = field("blocked-uri", "blockedURL"),
+            disposition = field("disposition", "disposition"),
+            "Content-Security-Policy violation"
+        );
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,154 @@
+use ::axum::extract::rejection::JsonRejection;
+use ::axum::http::{header, StatusCode};
+use ::axum::response::{IntoResponse, Response};
+use ::axum::Json;
+use ::serde::Serialize;
+use ::std::time::Duration;
+use ::tracing::error;
+
+use crate::rate_limit::ceil_seconds;
+
+/// Errors returned by API handlers.
+///
+/// All variants are rendered as a JSON envelope of the form
+/// `{"error": {"code": "...", "message": "..."}}`, so that the UI can handle them uniformly.
+#[derive(Debug)]
+pub(crate) enum ApiError {
+    /// The request carries no (valid) GitHub user access token.
+    Unauthorized,
+    /// The signed-in user is not allowed to access the requested resource.
+    Forbidden(String),
+    /// An unsafe request with the session cookie lacks a valid CSRF token (see `crate::csrf`).
+    CsrfTokenInvalid,
+    /// The request is malformed, or its body does not match the expected schema.
+    InvalidRequest(String),
+    /// The request body exceeds the size limit of the route.
+    PayloadTooLarge(String),
+    /// An `If-Match` precondition does not match the current version of a resource.
+    PreconditionFailed,
+    /// Modifying an existing resource requires an `If-Match` header.
+    PreconditionRequired,
+    /// The client has sent too many requests, and should wait this long before trying again.
+    RateLimited { retry_after: Duration },
+    /// The server is handling too many requests, and the client should try again after this long.
+    Overloaded { retry_after: Duration },
+    /// The request took longer than the timeout of its route.
+    Timeout(Duration),
+    /// An upstream service (e.g. GitHub) could not be reached or responded unexpectedly.
+    Upstream(String),
+    /// An upstream service (e.g. GitHub) did not respond in time.
+    UpstreamTimeout(String),
+    /// Something went wrong on our side. Details are logged, but not exposed to the client.
+    Internal(anyhow::Error),
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    error: ErrorBody<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    message: String,
+}
+
+impl ApiError {
+    fn status_and_code(&self) -> (StatusCode, &'static str) {
+        match self {
+            ApiError::Unauthorized => (StatusCode::UNAUTHORIZED, "unauthorized"),
+            ApiError::Forbidden(_) => (StatusCode::FORBIDDEN, "forbidden"),
+            ApiError::CsrfTokenInvalid => (StatusCode::FORBIDDEN, "csrf_token_invalid"),
+            ApiError::InvalidRequest(_) => (StatusCode::UNPROCESSABLE_ENTITY, "invalid_request"),
+            ApiError::PayloadTooLarge(_) => (StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large"),
+            ApiError::PreconditionFailed => {
+                (StatusCode::PRECONDITION_FAILED, "precondition_failed")
+            }
+            ApiError::PreconditionRequired => {
+                (StatusCode::PRECONDITION_REQUIRED, "precondition_required")
+            }
+            ApiError::RateLimited { .. } => (StatusCode::TOO_MANY_REQUESTS, "rate_limited"),
+            ApiError::Overloaded { .. } => (StatusCode::SERVICE_UNAVAILABLE, "overloaded"),
+            ApiError::Timeout(_) => (StatusCode::SERVICE_UNAVAILABLE, "timeout"),
+            ApiError::Upstream(_) => (StatusCode::BAD_GATEWAY, "upstream_error"),
+            ApiError::UpstreamTimeout(_) => (StatusCode::GATEWAY_TIMEOUT, "upstream_timeout"),
+            ApiError::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, "internal_error"),
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            ApiError::Unauthorized => "Sign in with GitHub to use this endpoint".to_string(),
+            ApiError::CsrfTokenInvalid => {
+                "Send the token from `GET /api/csrf-token` in an `X-CSRF-Token` header".to_string()
+            }
+            ApiError::Forbidden(message)
+            | ApiError::InvalidRequest(message)
+            | ApiError::PayloadTooLarge(message)
+            | ApiError::Upstream(message)
+            | ApiError::UpstreamTimeout(message) => message.clone(),
+            ApiError::PreconditionFailed => {
+                "The resource has been modified in the meantime. Reload it and try again"
+                    .to_string()
+            }
+            ApiError::PreconditionRequired => {
+                "Send the `ETag` of the current version in an `If-Match` header".to_string()
+            }
+            ApiError::RateLimited { retry_after } => format!(
+                "Too many requests. Try again in {} seconds",
+                ceil_seconds(*retry_after)
+            ),
+            ApiError::Overloaded { retry_after } => format!(
+                "The server is busy. Try again in {} seconds",
+                ceil_seconds(*retry_after)
+            ),
+            ApiError::Timeout(timeout) => format!(
+                "The request did not complete within {} seconds",
+                ceil_seconds(*timeout)
+            ),
+            ApiError::Internal(_) => "Internal server error".to_string(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match &self {
+            ApiError::Internal(e) => error!("Internal error: {e:#}"),
+            ApiError::Upstream(message) | ApiError::UpstreamTimeout(message) => {
+                error!("Upstream error: {message}")
+            }
+            _ => (),
+        }
+        let (status, code) = self.status_and_code();
+        let envelope = Envelope {
+            error: ErrorBody {
+                code,
+                message: self.message(),
+            },
+        };
+        let mut response = (status, Json(envelope)).into_response();
+        if let ApiError::RateLimited { retry_after } | ApiError::Overloaded { retry_after } = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, ceil_seconds(retry_after).into());
+        }
+        response
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            ApiError::PayloadTooLarge(rejection.body_text())
+        } else {
+            ApiError::InvalidRequest(rejection.body_text())
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        ApiError::Internal(e)
+    }
+}
@@ -0,0 +1,128 @@
+//! Server-Sent Events stream of broker [`Event`]s, for clients behind proxies that do not let
+//! WebSocket upgrades through.
+//!
+//! `GET /api/events?topics=<topic>,<topic>…` streams the events of the given topics, as
+//! `message` events whose data is `{"eventId": 7, "topic": "…", "data": …}`. Each event carries
+//! its ID, so that `EventSource` resumes with a `Last-Event-ID` header after a reconnect. If
+//! events have been missed in the meantime (the server only buffers the most recent ones), the
+//! stream starts with a `resync` event, upon which the client should reload its state. Without
+//! `Last-Event-ID`, the stream starts with the next event.
+//!
+//! The stream ends when the server shuts down. `EventSource` then reconnects by itself.
+
+use ::axum::extract::rejection::QueryRejection;
+use ::axum::extract::{Query, State};
+use ::axum::http::HeaderMap;
+use ::axum::response::sse::{self, KeepAlive, Sse};
+use ::futures_util::stream::{self, Stream, StreamExt};
+use ::serde::{Deserialize, Serialize};
+use ::std::collections::HashSet;
+use ::std::convert::Infallible;
+use ::std::time::Duration;
+use ::tokio::sync::broadcast;
+use ::tracing::{info, warn};
+
+use super::ApiError;
+use crate::auth::User;
+use crate::broker::{self, Broker, Event, Resumed};
+use crate::shutdown::Shutdown;
+
+/// Proxies tend to drop idle connections. A comment line every now and then keeps them busy.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Deserialize)]
+pub(super) struct EventsQuery {
+    /// Comma-separated list of topics
+    topics: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Payload {
+    event_id: u64,
+    topic: String,
+    data: serde_json::Value,
+}
+
+pub(super) async fn stream(
+    user: User,
+    State(broker): State<Broker>,
+    State(shutdown): State<Shutdown>,
+    query: Result<Query<EventsQuery>, QueryRejection>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, Infallible>>>, ApiError> {
+    let Query(query) = query.map_err(|e| ApiError::InvalidRequest(e.body_text()))?;
+    let topics = query
+        .topics
+        .split(',')
+        .map(str::trim)
+        .filter(|topic| !topic.is_empty())
+        .map(str::to_string)
+        .collect::<HashSet<_>>();
+    if topics.is_empty() {
+        return Err(ApiError::InvalidRequest("No topics given".to_string()));
+    }
+    if let Some(topic) = topics
+        .iter()
+        .find(|topic| !broker::may_subscribe(&user, topic))
+    {
+        return Err(ApiError::Forbidden(format!(
+            "Not allowed to subscribe to {topic}"
+        )));
+    }
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    info!(user_id = user.id, last_event_id, "SSE stream started");
+
+    let Resumed {
+        replayed,
+        is_complete,
+        receiver,
+    } = broker.resume_after(last_event_id);
+    let resync = (!is_complete).then(|| sse::Event::default().event("resync").data("{}"));
+    let replayed = replayed
+        .into_iter()
+        .filter(|event| topics.contains(&event.topic))
+        .map(to_sse_event)
+        .collect::<Vec<_>>();
+    let live = stream::unfold((receiver, topics), |(mut receiver, topics)| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) if topics.contains(&event.topic) => {
+                    return Some((to_sse_event(event), (receiver, topics)))
+                }
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    // Ending the stream makes the browser reconnect, and resume from the replay
+                    // buffer.
+                    warn!("SSE subscriber lagging behind, missed {missed} events");
+                    return None;
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    let events = stream::iter(resync.into_iter().chain(replayed))
+        .chain(live)
+        .take_until(async move { shutdown.triggered().await })
+        .map(Ok);
+    Ok(Sse::new(events).keep_alive(
+        KeepAlive::new()
+            .interval(KEEP_ALIVE_INTERVAL)
+            .text("keep-alive"),
+    ))
+}
+
+fn to_sse_event(event: Event) -> sse::Event {
+    let id = event.id.to_string();
+    sse::Event::default()
+        .id(id)
+        .json_data(Payload {
+            event_id: event.id,
+            topic: event.topic,
+            data: event.data,
+        })
+        .expect("JSON values are always serializable")
+}
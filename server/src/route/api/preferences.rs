@@ -0,0 +1,410 @@
+//! Per-user preferences (theme, language, layout), persisted on the server so that they follow the
+//! user across browsers.
+
+use ::axum::extract::rejection::JsonRejection;
+use ::axum::extract::{DefaultBodyLimit, State};
+use ::axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use ::axum::response::{IntoResponse, Response};
+use ::axum::routing::get;
+use ::axum::{Json, Router};
+use ::futures_util::future::{self, BoxFuture};
+use ::serde::{Deserialize, Serialize};
+use ::std::collections::HashMap;
+use ::std::sync::{Arc, Mutex};
+
+use super::{ApiError, ApiState};
+use crate::auth::User;
+use crate::broker::{self, Broker};
+
+/// Preferences documents are tiny. Anything bigger than this is rejected without being parsed.
+const MAX_PREFERENCES_BYTES: usize = 4 * 1024;
+
+pub(super) fn router() -> Router<ApiState> {
+    Router::new()
+        .route("/", get(get_preferences).put(put_preferences))
+        .layer(DefaultBodyLimit::max(MAX_PREFERENCES_BYTES))
+}
+
+/// The preferences document of a user.
+///
+/// All fields are optional, so that the UI can fall back to its defaults for anything the user
+/// has not chosen yet. Unknown fields are rejected, which keeps this struct the single source of
+/// truth for the schema.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub(crate) struct Preferences {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    theme: Option<Theme>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    language: Option<LanguageTag>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    layout: Option<Layout>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum Theme {
+    Light,
+    Dark,
+    System,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum Layout {
+    Compact,
+    Comfortable,
+}
+
+/// A (simplified) BCP 47 language tag, such as `en` or `de-CH`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+struct LanguageTag(String);
+
+impl TryFrom<String> for LanguageTag {
+    type Error = String;
+
+    fn try_from(tag: String) -> Result<Self, Self::Error> {
+        let mut subtags = tag.split('-');
+        let primary_is_valid = subtags.next().is_some_and(|primary| {
+            (2..=3).contains(&primary.len()) && primary.chars().all(|c| c.is_ascii_alphabetic())
+        });
+        let rest_is_valid = subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+        if primary_is_valid && rest_is_valid {
+            Ok(LanguageTag(tag))
+        } else {
+            Err(format!("`{tag}` is not a valid language tag"))
+        }
+    }
+}
+
+impl From<LanguageTag> for String {
+    fn from(tag: LanguageTag) -> Self {
+        tag.0
+    }
+}
+
+/// A stored document together with its version. The version is exposed as the `ETag`.
+#[derive(Clone, Debug)]
+pub(crate) struct Versioned<T> {
+    pub version: u64,
+    pub document: T,
+}
+
+/// Outcome of [`PreferencesStore::store`].
+pub(crate) enum StoreOutcome {
+    Stored {
+        version: u64,
+    },
+    /// The stored version is not the expected one, i.e. someone else was faster.
+    VersionMismatch,
+}
+
+/// Persistence of [`Preferences`], keyed by GitHub user ID.
+///
+/// There is no database yet. [`InMemoryPreferencesStore`] is the only implementation for now, but
+/// handlers only depend on this trait.
+pub(crate) trait PreferencesStore: Send + Sync {
+    fn load(&self, user_id: u64) -> BoxFuture<'_, anyhow::Result<Option<Versioned<Preferences>>>>;
+
+    /// Stores `preferences` if the current version of the document is `expected_version`
+    /// (`None`: there must be no document yet).
+    fn store(
+        &self,
+        user_id: u64,
+        expected_version: Option<u64>,
+        preferences: Preferences,
+    ) -> BoxFuture<'_, anyhow::Result<StoreOutcome>>;
+
+    /// Fails if the backend cannot be reached.
+    fn check_health(&self) -> BoxFuture<'_, anyhow::Result<()>>;
+}
+
+/// Keeps preferences in memory. They are lost when the server restarts.
+#[derive(Default)]
+pub(crate) struct InMemoryPreferencesStore {
+    documents: Mutex<HashMap<u64, Versioned<Preferences>>>,
+}
+
+impl PreferencesStore for InMemoryPreferencesStore {
+    fn load(&self, user_id: u64) -> BoxFuture<'_, anyhow::Result<Option<Versioned<Preferences>>>> {
+        let document = self.documents.lock().unwrap().get(&user_id).cloned();
+        Box::pin(future::ready(Ok(document)))
+    }
+
+    fn store(
+        &self,
+        user_id: u64,
+        expected_version: Option<u64>,
+        preferences: Preferences,
+    ) -> BoxFuture<'_, anyhow::Result<StoreOutcome>> {
+        let mut documents = self.documents.lock().unwrap();
+        let current_version = documents.get(&user_id).map(|current| current.version);
+        let outcome = if current_version == expected_version {
+            let version = current_version.unwrap_or(0) + 1;
+            documents.insert(
+                user_id,
+                Versioned {
+                    version,
+                    document: preferences,
+                },
+            );
+            StoreOutcome::Stored { version }
+        } else {
+            StoreOutcome::VersionMismatch
+        };
+        Box::pin(future::ready(Ok(outcome)))
+    }
+
+    fn check_health(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(future::ready(Ok(())))
+    }
+}
+
+async fn get_preferences(
+    State(store): State<Arc<dyn PreferencesStore>>,
+    user: User,
+) -> Result<Response, ApiError> {
+    Ok(match store.load(user.id).await? {
+        Some(Versioned { version, document }) => {
+            ([(header::ETAG, etag(version))], Json(document)).into_response()
+        }
+        // Nothing stored yet. An empty document means "defaults everywhere".
+        None => Json(Preferences::default()).into_response(),
+    })
+}
+
+/// Stores the preferences, and announces them on the `user/<user ID>/preferences` topic, so that
+/// other sessions of the same user can follow.
+async fn put_preferences(
+    State(store): State<Arc<dyn PreferencesStore>>,
+    State(broker): State<Broker>,
+    user: User,
+    headers: HeaderMap,
+    preferences: Result<Json<Preferences>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Json(preferences) = preferences?;
+    let current_version = store.load(user.id).await?.map(|current| current.version);
+    match (current_version, IfMatch::from_headers(&headers)?) {
+        (None, IfMatch::Absent) => (),
+        (Some(_), IfMatch::Absent) => return Err(ApiError::PreconditionRequired),
+        (Some(_), IfMatch::Any) => (),
+        (Some(version), IfMatch::Versions(versions)) if versions.contains(&version) => (),
+        _ => return Err(ApiError::PreconditionFailed),
+    }
+    match store
+        .store(user.id, current_version, preferences.clone())
+        .await?
+    {
+        StoreOutcome::Stored { version } => {
+            broker.publish(
+                &broker::user_topic(user.id, "preferences"),
+                PreferencesChanged {
+                    etag: format!("\"{version}\""),
+                    preferences: &preferences,
+                },
+            );
+            let status = if current_version.is_some() {
+                StatusCode::OK
+            } else {
+                StatusCode::CREATED
+            };
+            Ok((status, [(header::ETAG, etag(version))], Json(preferences)).into_response())
+        }
+        StoreOutcome::VersionMismatch => Err(ApiError::PreconditionFailed),
+    }
+}
+
+#[derive(Serialize)]
+struct PreferencesChanged<'a> {
+    etag: String,
+    preferences: &'a Preferences,
+}
+
+fn etag(version: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{version}\"")).unwrap() // digits are always valid
+}
+
+/// The parsed `If-Match` header of a request.
+enum IfMatch {
+    Absent,
+    Any,
+    /// Versions taken from strong entity tags. (`If-Match` requires strong comparison, so weak
+    /// tags never match.)
+    Versions(Vec<u64>),
+}
+
+impl IfMatch {
+    fn from_headers(headers: &HeaderMap) -> Result<Self, ApiError> {
+        let Some(value) = headers.get(header::IF_MATCH) else {
+            return Ok(IfMatch::Absent);
+        };
+        let value = value
+            .to_str()
+            .map_err(|_| ApiError::InvalidRequest("Malformed `If-Match` header".to_string()))?;
+        if value.trim() == "*" {
+            return Ok(IfMatch::Any);
+        }
+        Ok(IfMatch::Versions(
+            value
+                .split(',')
+                .filter_map(|tag| {
+                    tag.trim()
+                        .strip_prefix('"')?
+                        .strip_suffix('"')?
+                        .parse()
+                        .ok()
+                })
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::reqwest::Url;
+    use ::serde_json::{json, Value};
+
+    use super::*;
+    use crate::auth::Authenticator;
+    use crate::http_client;
+    use crate::test_support;
+
+    const OCTOCAT: &str = "gho_octocat";
+    const HUBOT: &str = "gho_hubot";
+
+    /// Serves the preferences of two signed-in users, and returns the URL.
+    async fn serve() -> String {
+        // The users are known, so GitHub is not asked.
+        let github = Url::parse("http://github.invalid").unwrap();
+        let authenticator = Authenticator::new(http_client::tests::client(github));
+        for (id, token, login) in [(1, OCTOCAT, "octocat"), (2, HUBOT, "hubot")] {
+            let user = User {
+                id,
+                login: login.to_string(),
+            };
+            authenticator.remember(token, user);
+        }
+        let router = Router::new()
+            .nest("/preferences", router())
+            .with_state(ApiState::for_tests(authenticator));
+        format!("http://{}/preferences", test_support::serve(router).await)
+    }
+
+    struct Put {
+        status: StatusCode,
+        etag: Option<String>,
+    }
+
+    async fn put(url: &str, token: &str, if_match: Option<&str>, body: Value) -> Put {
+        let mut request = reqwest::Client::new()
+            .put(url)
+            .bearer_auth(token)
+            .json(&body);
+        if let Some(if_match) = if_match {
+            request = request.header(header::IF_MATCH, if_match);
+        }
+        let response = request.send().await.unwrap();
+        let etag = etag_of(response.headers());
+        Put {
+            status: response.status(),
+            etag,
+        }
+    }
+
+    fn etag_of(headers: &HeaderMap) -> Option<String> {
+        headers
+            .get(header::ETAG)
+            .map(|etag| etag.to_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn updates_require_the_current_etag() {
+        let url = serve().await;
+        let dark = json!({ "theme": "dark" });
+
+        let created = put(&url, OCTOCAT, None, dark.clone()).await;
+        assert_eq!(created.status, StatusCode::CREATED);
+        assert_eq!(created.etag.as_deref(), Some("\"1\""));
+
+        let unconditional = put(&url, OCTOCAT, None, dark.clone()).await;
+        assert_eq!(unconditional.status, StatusCode::PRECONDITION_REQUIRED);
+        for stale in ["\"0\"", "\"2\"", "W/\"1\"", "1", "\"x\""] {
+            let response = put(&url, OCTOCAT, Some(stale), dark.clone()).await;
+            assert_eq!(response.status, StatusCode::PRECONDITION_FAILED, "{stale}");
+        }
+
+        let updated = put(&url, OCTOCAT, Some("\"1\""), json!({ "layout": "compact" })).await;
+        assert_eq!(updated.status, StatusCode::OK);
+        assert_eq!(updated.etag.as_deref(), Some("\"2\""));
+        let updated = put(&url, OCTOCAT, Some("W/\"2\", \"7\", \"2\""), dark.clone()).await;
+        assert_eq!(updated.status, StatusCode::OK);
+        assert_eq!(updated.etag.as_deref(), Some("\"3\""));
+        let updated = put(&url, OCTOCAT, Some("*"), json!({ "language": "de-CH" })).await;
+        assert_eq!(updated.status, StatusCode::OK);
+        assert_eq!(updated.etag.as_deref(), Some("\"4\""));
+
+        let response = reqwest::Client::new()
+            .get(&url)
+            .bearer_auth(OCTOCAT)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(etag_of(response.headers()).as_deref(), Some("\"4\""));
+        let stored: Value = response.json().await.unwrap();
+        assert_eq!(stored, json!({ "language": "de-CH" }));
+    }
+
+    #[tokio::test]
+    async fn each_user_has_their_own_document() {
+        let url = serve().await;
+        let dark = json!({ "theme": "dark" });
+        assert_eq!(
+            put(&url, OCTOCAT, None, dark.clone()).await.status,
+            StatusCode::CREATED
+        );
+
+        // `*` only matches a document that exists.
+        let response = put(&url, HUBOT, Some("*"), dark.clone()).await;
+        assert_eq!(response.status, StatusCode::PRECONDITION_FAILED);
+        let response = put(&url, HUBOT, Some("\"1\""), dark.clone()).await;
+        assert_eq!(response.status, StatusCode::PRECONDITION_FAILED);
+
+        let response = reqwest::Client::new()
+            .get(&url)
+            .bearer_auth(HUBOT)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(etag_of(response.headers()), None);
+        assert_eq!(response.json::<Value>().await.unwrap(), json!({}));
+    }
+
+    #[tokio::test]
+    async fn invalid_documents_are_rejected() {
+        let url = serve().await;
+        for body in [
+            json!({ "theme": "sepia" }),
+            json!({ "language": "english" }),
+            json!({ "fontSize": 12 }),
+        ] {
+            let response = put(&url, OCTOCAT, None, body.clone()).await;
+            assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY, "{body}");
+        }
+        let response = reqwest::Client::new()
+            .put(&url)
+            .bearer_auth(OCTOCAT)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(format!(
+                "{{\"theme\": \"{}\"}}",
+                "a".repeat(MAX_PREFERENCES_BYTES)
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
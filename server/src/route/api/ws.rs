@@ -0,0 +1,209 @@
+//! WebSocket channel for pushing [`Event`]s to the UI.
+//!
+//! All messages are JSON objects, distinguished by their `type`:
+//!
+//! | direction       | message                                                   |
+//! |-----------------|-----------------------------------------------------------|
+//! | client → server | `{"type": "subscribe", "id": 1, "topic": "public/news"}`  |
+//! | client → server | `{"type": "unsubscribe", "id": 2, "topic": "public/news"}`|
+//! | server → client | `{"type": "ack", "id": 1}`                                |
+//! | server → client | `{"type": "error", "id": 1, "message": "…"}`              |
+//! | server → client | `{"type": "event", "eventId": 7, "topic": "…", "data": …}`|
+//!
+//! `id` is chosen by the client, and only serves to correlate `ack`s and `error`s with requests.
+//! The server sends a WebSocket ping every [`HEARTBEAT_INTERVAL`], and closes connections that
+//! stay silent for longer than [`HEARTBEAT_TIMEOUT`]. Browsers answer pings automatically. When
+//! the server shuts down, it closes every connection with code 1001 ("going away").
+
+use ::axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use ::axum::extract::State;
+use ::axum::response::Response;
+use ::serde::{Deserialize, Serialize};
+use ::std::collections::HashSet;
+use ::std::time::Duration;
+use ::tokio::sync::broadcast;
+use ::tokio::time::{self, Instant};
+use ::tracing::{debug, info, warn};
+
+use crate::auth::User;
+use crate::broker::{self, Broker, Event};
+use crate::metrics::WebSocketConnectionGuard;
+use crate::shutdown::Shutdown;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(75);
+
+/// WebSocket close code for "policy violation".
+const CLOSE_POLICY_VIOLATION: u16 = 1008;
+
+/// WebSocket close code for "going away", such as a server that shuts down.
+const CLOSE_GOING_AWAY: u16 = 1001;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ClientMessage {
+    Subscribe { id: u64, topic: String },
+    Unsubscribe { id: u64, topic: String },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ServerMessage {
+    Ack {
+        id: u64,
+    },
+    Error {
+        id: Option<u64>,
+        message: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Event {
+        event_id: u64,
+        topic: String,
+        data: serde_json::Value,
+    },
+}
+
+impl From<Event> for ServerMessage {
+    fn from(event: Event) -> Self {
+        ServerMessage::Event {
+            event_id: event.id,
+            topic: event.topic,
+            data: event.data,
+        }
+    }
+}
+
+pub(super) async fn upgrade(
+    ws: WebSocketUpgrade,
+    user: User,
+    State(broker): State<Broker>,
+    State(shutdown): State<Shutdown>,
+) -> Response {
+    ws.on_failed_upgrade(|err| warn!("Error upgrading WebSocket: {err}"))
+        .on_upgrade(move |socket| {
+            // Tracked, so that the process waits for the close frame on shutdown
+            shutdown.track(Session::new(user, &broker, shutdown.clone()).run(socket))
+        })
+}
+
+/// The state of one WebSocket connection.
+struct Session {
+    user: User,
+    topics: HashSet<String>,
+    events: broadcast::Receiver<Event>,
+    shutdown: Shutdown,
+}
+
+impl Session {
+    fn new(user: User, broker: &Broker, shutdown: Shutdown) -> Self {
+        Self {
+            user,
+            topics: HashSet::new(),
+            events: broker.subscribe(),
+            shutdown,
+        }
+    }
+
+    async fn run(mut self, mut socket: WebSocket) {
+        info!(user_id = self.user.id, "WebSocket session started");
+        let _connection_guard = WebSocketConnectionGuard::new();
+        let mut heartbeat = time::interval(HEARTBEAT_INTERVAL);
+        let mut last_seen = Instant::now();
+        loop {
+            let outgoing = tokio::select! {
+                incoming = socket.recv() => match incoming {
+                    Some(Ok(message)) => {
+                        last_seen = Instant::now();
+                        match self.handle(message) {
+                            Ok(Some(reply)) => Message::from(reply),
+                            Ok(None) => continue,
+                            Err(close_frame) => Message::Close(Some(close_frame)),
+                        }
+                    }
+                    Some(Err(err)) => {
+                        debug!("Error receiving WebSocket message: {err}");
+                        break;
+                    }
+                    None => break,
+                },
+                event = self.events.recv() => match event {
+                    Ok(event) if self.topics.contains(&event.topic) => Message::from(ServerMessage::from(event)),
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!(user_id = self.user.id, "WebSocket subscriber lagging behind, missed {missed} events");
+                        Message::from(ServerMessage::Error {
+                            id: None,
+                            message: format!("Missed {missed} events"),
+                        })
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                () = self.shutdown.triggered() => Message::Close(Some(CloseFrame {
+                    code: CLOSE_GOING_AWAY,
+                    reason: "the server is shutting down".into(),
+                })),
+                _ = heartbeat.tick() => {
+                    if last_seen.elapsed() > HEARTBEAT_TIMEOUT {
+                        info!(user_id = self.user.id, "WebSocket peer is unresponsive");
+                        break;
+                    }
+                    Message::Ping(Vec::new())
+                }
+            };
+            let is_close = matches!(outgoing, Message::Close(_));
+            if let Err(err) = socket.send(outgoing).await {
+                debug!("Error sending WebSocket message: {err}");
+                break;
+            }
+            if is_close {
+                break;
+            }
+        }
+        info!(user_id = self.user.id, "WebSocket session ended");
+    }
+
+    /// Handles a message from the client, and returns the reply, if any. `Err` means that the
+    /// connection must be closed.
+    fn handle(&mut self, message: Message) -> Result<Option<ServerMessage>, CloseFrame<'static>> {
+        let text = match message {
+            Message::Text(text) => text,
+            // Pongs only matter as a sign of life. Pings are answered by axum.
+            Message::Ping(_) | Message::Pong(_) | Message::Close(_) => return Ok(None),
+            Message::Binary(_) => {
+                return Err(CloseFrame {
+                    code: CLOSE_POLICY_VIOLATION,
+                    reason: "binary messages are not supported".into(),
+                })
+            }
+        };
+        Ok(Some(match serde_json::from_str::<ClientMessage>(&text) {
+            Ok(ClientMessage::Subscribe { id, topic }) => {
+                if broker::may_subscribe(&self.user, &topic) {
+                    self.topics.insert(topic);
+                    ServerMessage::Ack { id }
+                } else {
+                    ServerMessage::Error {
+                        id: Some(id),
+                        message: format!("Not allowed to subscribe to {topic}"),
+                    }
+                }
+            }
+            Ok(ClientMessage::Unsubscribe { id, topic }) => {
+                self.topics.remove(&topic);
+                ServerMessage::Ack { id }
+            }
+            Err(e) => ServerMessage::Error {
+                id: None,
+                message: format!("Invalid message: {e}"),
+            },
+        }))
+    }
+}
+
+impl From<ServerMessage> for Message {
+    fn from(message: ServerMessage) -> Self {
+        // Serializing these simple types cannot fail.
+        Message::Text(serde_json::to_string(&message).unwrap())
+    }
+}
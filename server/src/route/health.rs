@@ -0,0 +1,106 @@
+//! Probes for the hosting platform and for humans: liveness (`/healthz`), readiness (`/readyz`)
+//! and build information (`/version`).
+
+use ::axum::extract::State;
+use ::axum::http::StatusCode;
+use ::axum::routing::get;
+use ::axum::{Json, Router};
+use ::serde::Serialize;
+use ::std::collections::BTreeMap;
+use ::std::path::Path;
+use ::std::sync::Arc;
+use ::tracing::warn;
+
+use super::api::PreferencesStore;
+use super::spa::UiFiles;
+
+/// What `/readyz` checks.
+#[derive(Clone)]
+pub(crate) struct Readiness {
+    /// The files of the SPA. Without their `index.html`, the UI only yields 404s.
+    pub ui_files: UiFiles,
+    /// Stands in for the database, until there is one.
+    pub preferences: Arc<dyn PreferencesStore>,
+}
+
+pub(crate) fn router(readiness: Readiness) -> Router<()> {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/version", get(version))
+        .with_state(readiness)
+}
+
+/// The process is up and serving requests. Nothing else is checked.
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+#[derive(Serialize)]
+struct ReadinessReport {
+    ready: bool,
+    checks: BTreeMap<&'static str, CheckResult>,
+}
+
+#[derive(Serialize)]
+struct CheckResult {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl From<Result<(), String>> for CheckResult {
+    fn from(result: Result<(), String>) -> Self {
+        CheckResult {
+            ok: result.is_ok(),
+            error: result.err(),
+        }
+    }
+}
+
+async fn readyz(State(readiness): State<Readiness>) -> (StatusCode, Json<ReadinessReport>) {
+    let ui = if readiness.ui_files.is_file(Path::new("index.html")) {
+        Ok(())
+    } else {
+        Err(format!("index.html not found in {}", readiness.ui_files))
+    };
+    let database = readiness
+        .preferences
+        .check_health()
+        .await
+        .map_err(|e| format!("{e:#}"));
+    // Secrets and upstream URLs are not checked: the server does not start with an invalid
+    // configuration, and a reload that fails keeps the previous one. GitHub being down is not
+    // checked either, as it would take the UI out of service, too.
+    let checks = BTreeMap::from([("ui", CheckResult::from(ui)), ("database", database.into())]);
+    let ready = checks.values().all(|check| check.ok);
+    if !ready {
+        warn!(
+            "Not ready: {}",
+            serde_json::to_string(&checks).unwrap_or_default()
+        );
+    }
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(ReadinessReport { ready, checks }))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Version {
+    version: &'static str,
+    git_commit: &'static str,
+    build_timestamp: &'static str,
+}
+
+async fn version() -> Json<Version> {
+    Json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        // Both are set by `build.rs`.
+        git_commit: env!("GIT_COMMIT"),
+        build_timestamp: env!("BUILD_TIMESTAMP"),
+    })
+}
@@ -1,11 +1,429 @@
-use ::std::path::PathBuf;
-use ::tower_http::services::{ServeDir, ServeFile};
+use ::axum::extract::{Request, State};
+use ::axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use ::axum::middleware::{self, Next};
+use ::axum::response::{IntoResponse, Response};
+use ::axum::routing::get;
+use ::axum::Router;
+use ::globset::{Glob, GlobMatcher};
+use ::percent_encoding::percent_decode_str;
+use ::std::path::{Component, Path, PathBuf};
+use ::std::sync::Arc;
+use ::tower_http::compression::CompressionLayer;
+use ::tower_http::services::ServeDir;
+use ::tower_http::set_header::SetResponseHeaderLayer;
+use ::tracing::{debug, info, warn};
 
-pub(crate) fn serve_dir(directory_path: PathBuf) -> ServeDir<ServeFile> {
+use self::index_html::IndexHtml;
+pub(crate) use self::ui_files::UiFiles;
+use crate::config::{ConfigValue, SharedConfig};
+
+#[cfg(feature = "embed-ui")]
+mod embedded;
+mod index_html;
+mod ui_files;
+
+/// The `Cache-Control` header of the files of the UI, as configured with `SPA_CACHE_RULES`: rules
+/// of the form `glob=cache-control`, separated by `;`. The globs match the path relative to the
+/// root of the UI, and the first matching rule wins. `index.html` is never cached, because it is
+/// rendered for every request.
+#[derive(Clone, Debug)]
+pub(crate) struct CacheRules(Vec<(GlobMatcher, HeaderValue)>);
+
+/// elm-land fingerprints the file names in `assets/`, so they never change. Everything else must
+/// be revalidated, so that a deployment takes effect immediately.
+pub(crate) const DEFAULT_CACHE_RULES: &str =
+    "assets/**=public, max-age=31536000, immutable; **=no-cache";
+
+impl CacheRules {
+    fn cache_control(&self, relative_path: &Path) -> Option<HeaderValue> {
+        self.0
+            .iter()
+            .find(|(glob, _)| glob.is_match(relative_path))
+            .map(|(_, cache_control)| cache_control.clone())
+    }
+}
+
+impl ConfigValue for CacheRules {
+    fn parse(value: &str) -> Result<Self, String> {
+        value
+            .split(';')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .map(|rule| {
+                let invalid =
+                    || format!("{rule} must be a rule such as assets/**=public, max-age=31536000");
+                let (glob, cache_control) = rule.split_once('=').ok_or_else(invalid)?;
+                let glob = Glob::new(glob.trim())
+                    .map_err(|e| format!("{rule} has an invalid glob: {e}"))?
+                    .compile_matcher();
+                let cache_control = Some(cache_control.trim())
+                    .filter(|cache_control| !cache_control.is_empty())
+                    .and_then(|cache_control| HeaderValue::from_str(cache_control).ok())
+                    .ok_or_else(invalid)?;
+                Ok((glob, cache_control))
+            })
+            .collect::<Result<_, _>>()
+            .map(CacheRules)
+    }
+}
+
+pub(crate) fn router(ui_files: UiFiles, config: SharedConfig) -> Router {
     // The served directory contains a single page app (SPA), and 'index.html' is the HTML file to
     // be served by default. Even URLs to (virtual) sub-paths must be resolved to the same app.
     // Routing to the virtual path will be done by the JavaScript of the SPA.
-    let mut path_to_index_html = directory_path.clone();
-    path_to_index_html.push("index.html");
-    ServeDir::new(directory_path).fallback(ServeFile::new(path_to_index_html))
+    if !ui_files.is_file(Path::new("index.html")) {
+        // Without this warning, the only symptom would be 404s for every page.
+        warn!("{ui_files} has no index.html. Build the UI with `make build_ui`");
+    }
+    let caching = Caching {
+        ui_files: ui_files.clone(),
+        config: config.clone(),
+    };
+    let index_html = IndexHtml::new(ui_files.clone(), config);
+    let router = Router::new()
+        .route("/", get(index_html::serve))
+        .route("/index.html", get(index_html::serve));
+    let router = match ui_files {
+        UiFiles::Directory(directory_path) => {
+            let spa_fallback = Router::new()
+                .fallback(index_html::serve)
+                .layer(middleware::from_fn(navigation_only))
+                .with_state(index_html.clone());
+            // `make build_ui` stores `.br` and `.gz` siblings of the compiled files. They are
+            // served instead of the original, if the client accepts the encoding. Anything else
+            // is compressed on the fly.
+            router.fallback_service(
+                ServeDir::new(directory_path)
+                    .append_index_html_on_directories(false)
+                    .precompressed_br()
+                    .precompressed_gzip()
+                    .fallback(spa_fallback),
+            )
+        }
+        #[cfg(feature = "embed-ui")]
+        UiFiles::Embedded => router.fallback(embedded::serve),
+    };
+    router
+        .with_state(index_html)
+        .layer(CompressionLayer::new())
+        // The compression layer only adds `Vary` to what it compresses itself. Every response
+        // depends on `Accept-Encoding` though, including precompressed ones and the uncompressed
+        // originals.
+        .layer(SetResponseHeaderLayer::if_not_present(
+            header::VARY,
+            HeaderValue::from_static("accept-encoding"),
+        ))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(caching),
+            set_cache_headers,
+        ))
+}
+
+/// Lets only navigations through to the SPA fallback. Other requests for missing files get a
+/// `404`: `index.html` instead of a script only yields a confusing syntax error in the browser.
+async fn navigation_only(request: Request, next: Next) -> Response {
+    if is_navigation(&request) {
+        next.run(request).await
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+/// Whether a request for a missing file is a page of the SPA: it has no file extension, or
+/// accepts HTML.
+fn is_navigation(request: &Request) -> bool {
+    let path = request.uri().path();
+    let has_extension = Path::new(path).extension().is_some();
+    let accepts_html = request
+        .headers()
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains("text/html"));
+    if accepts_html || !has_extension {
+        debug!(
+            path,
+            accepts_html, "Serving index.html for a page of the SPA"
+        );
+        true
+    } else {
+        info!(
+            path,
+            "No such file in the UI, and no navigation either: 404"
+        );
+        false
+    }
+}
+
+/// The file that `uri_path` names, relative to the root of the UI. It is percent-decoded as
+/// `ServeDir` does, so that both agree on the file. `None` if it leaves the root, or is not UTF-8.
+fn decoded_path(uri_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(uri_path.trim_start_matches('/'))
+        .decode_utf8()
+        .ok()?;
+    let mut relative_path = PathBuf::new();
+    for component in Path::new(&*decoded).components() {
+        match component {
+            // As in `ServeDir`, e.g. against a `C:` prefix on Windows
+            Component::Normal(name)
+                if Path::new(name)
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_))) =>
+            {
+                relative_path.push(name);
+            }
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(relative_path)
+}
+
+struct Caching {
+    ui_files: UiFiles,
+    /// Its `SPA_CACHE_RULES` apply to each request, so that a reload changes them.
+    config: SharedConfig,
+}
+
+impl Caching {
+    /// Returns the path of the file that answers a request for `uri_path`, relative to the root of
+    /// the UI.
+    fn served_file(&self, uri_path: &str) -> PathBuf {
+        let relative_path = decoded_path(uri_path).map(|mut relative_path| {
+            if uri_path.ends_with('/') {
+                relative_path.push("index.html");
+            }
+            relative_path
+        });
+        match relative_path.filter(|relative_path| self.ui_files.is_file(relative_path)) {
+            Some(relative_path) => relative_path,
+            // The SPA fallback, or a `404`
+            None => PathBuf::from("index.html"),
+        }
+    }
+
+    /// A strong `ETag`, derived from the version of the file (see [`UiFiles::version`]). The
+    /// encoding is part of it, because each encoding is a different representation.
+    fn etag(&self, relative_path: &Path, content_encoding: Option<&HeaderValue>) -> Option<String> {
+        let version = self.ui_files.version(relative_path)?;
+        let encoding = content_encoding
+            .and_then(|value| value.to_str().ok())
+            .map_or(String::new(), |encoding| format!("-{encoding}"));
+        Some(format!("\"{version}{encoding}\""))
+    }
+}
+
+async fn set_cache_headers(
+    State(caching): State<Arc<Caching>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let relative_path = caching.served_file(request.uri().path());
+    let is_get_or_head = matches!(*request.method(), Method::GET | Method::HEAD);
+    let if_none_match = request.headers_mut().remove(header::IF_NONE_MATCH);
+    if if_none_match.is_some() {
+        // `If-None-Match` takes precedence (RFC 9110, 13.2.2). `ServeDir` would answer
+        // `If-Modified-Since` on its own, without our `ETag`.
+        request.headers_mut().remove(header::IF_MODIFIED_SINCE);
+    }
+
+    let mut response = next.run(request).await;
+    if !(response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED)
+        // Such as the rendered `index.html`, which must not be cached at all
+        || response.headers().contains_key(header::CACHE_CONTROL)
+    {
+        return response;
+    }
+    let etag = caching
+        .etag(
+            &relative_path,
+            response.headers().get(header::CONTENT_ENCODING),
+        )
+        .and_then(|etag| HeaderValue::try_from(etag).ok());
+    let headers = response.headers_mut();
+    let cache_rules = &caching.config.current().spa_cache_rules;
+    if let Some(cache_control) = cache_rules.cache_control(&relative_path) {
+        headers.insert(header::CACHE_CONTROL, cache_control);
+    }
+    let Some(etag) = etag else {
+        return response;
+    };
+    headers.insert(header::ETAG, etag.clone());
+
+    if is_get_or_head && if_none_match.is_some_and(|value| matches_etag(&value, &etag)) {
+        let mut not_modified = StatusCode::NOT_MODIFIED.into_response();
+        copy_headers(
+            response.headers(),
+            not_modified.headers_mut(),
+            &[header::CACHE_CONTROL, header::ETAG, header::VARY],
+        );
+        return not_modified;
+    }
+    response
+}
+
+/// Compares with the weak comparison function, as required for `If-None-Match`.
+fn matches_etag(if_none_match: &HeaderValue, etag: &HeaderValue) -> bool {
+    let Ok(if_none_match) = if_none_match.to_str() else {
+        return false;
+    };
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = etag.to_str().map(opaque).unwrap_or_default();
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+fn copy_headers(from: &HeaderMap, to: &mut HeaderMap, names: &[header::HeaderName]) {
+    for name in names {
+        for value in from.get_all(name) {
+            to.append(name, value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::axum::http::HeaderName;
+    use ::reqwest::Response;
+
+    use super::*;
+    use crate::test_support;
+
+    const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+    fn cache_control(rules: &CacheRules, relative_path: &str) -> Option<HeaderValue> {
+        rules.cache_control(Path::new(relative_path))
+    }
+
+    #[test]
+    fn the_first_matching_cache_rule_wins() {
+        let rules = CacheRules::parse(DEFAULT_CACHE_RULES).unwrap();
+        assert_eq!(
+            cache_control(&rules, "assets/index-1a2b3c.js").unwrap(),
+            IMMUTABLE
+        );
+        assert_eq!(
+            cache_control(&rules, "assets/fonts/a.woff2").unwrap(),
+            IMMUTABLE
+        );
+        assert_eq!(cache_control(&rules, "favicon.ico").unwrap(), "no-cache");
+        assert_eq!(cache_control(&rules, "index.html").unwrap(), "no-cache");
+
+        let rules = CacheRules::parse("*.png = max-age=60 ;; images/** = no-store").unwrap();
+        assert_eq!(
+            cache_control(&rules, "images/logo.png").unwrap(),
+            "max-age=60"
+        );
+        assert_eq!(
+            cache_control(&rules, "images/logo.svg").unwrap(),
+            "no-store"
+        );
+        assert_eq!(cache_control(&rules, "index.html"), None);
+    }
+
+    #[test]
+    fn invalid_cache_rules_are_rejected() {
+        for rules in ["assets/**", "assets/[=no-cache", "**=", "**=no\ncache"] {
+            assert!(CacheRules::parse(rules).is_err(), "{rules:?} is accepted");
+        }
+        assert!(CacheRules::parse("").unwrap().0.is_empty());
+    }
+
+    const SPACED_JS: &str = "console.log('a b')";
+
+    /// Serves a UI with `index.html`, `assets/app.js` and `assets/a b.js`, and returns its base URL.
+    async fn serve_ui(name: &str, config: &[(&str, &str)]) -> String {
+        let directory = std::env::temp_dir().join(format!("spa-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("assets")).unwrap();
+        std::fs::write(directory.join("index.html"), "<html><head></head></html>").unwrap();
+        std::fs::write(directory.join("assets/app.js"), "console.log(1)").unwrap();
+        std::fs::write(directory.join("assets/a b.js"), SPACED_JS).unwrap();
+        let config = SharedConfig::for_tests(config);
+        let router = router(UiFiles::Directory(directory), config);
+        format!("http://{}/", test_support::serve(router).await)
+    }
+
+    async fn get(url: &str, headers: &[(HeaderName, &str)]) -> Response {
+        let mut request = reqwest::Client::new().get(url);
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        request.send().await.unwrap()
+    }
+
+    const SECRETS: (&str, &str) = ("GITHUB_APP_CLIENT_SECRET", "secret");
+
+    #[tokio::test]
+    async fn unchanged_files_are_not_sent_again() {
+        let url = format!("{}assets/app.js", serve_ui("etag", &[SECRETS]).await);
+        let response = get(&url, &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], IMMUTABLE);
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(
+            etag.starts_with('"') && etag.ends_with('"'),
+            "{etag} is not strong"
+        );
+
+        for if_none_match in [etag.clone(), format!("W/{etag}"), format!("\"x\", {etag}")] {
+            let response = get(&url, &[(header::IF_NONE_MATCH, &if_none_match)]).await;
+            assert_eq!(
+                response.status(),
+                StatusCode::NOT_MODIFIED,
+                "{if_none_match}"
+            );
+            assert_eq!(response.headers()[header::ETAG], *etag);
+            assert_eq!(response.headers()[header::CACHE_CONTROL], IMMUTABLE);
+        }
+
+        // `If-None-Match` takes precedence over `If-Modified-Since`.
+        let headers = [
+            (header::IF_NONE_MATCH, "\"outdated\""),
+            (header::IF_MODIFIED_SINCE, "Fri, 01 Jan 2100 00:00:00 GMT"),
+        ];
+        let response = get(&url, &headers).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "console.log(1)");
+    }
+
+    #[test]
+    fn paths_are_decoded_like_serve_dir_does() {
+        let decoded = |uri_path| decoded_path(uri_path).map(|path| path.display().to_string());
+        assert_eq!(decoded("/assets/a%20b.js").unwrap(), "assets/a b.js");
+        assert_eq!(decoded("/assets/./app.js").unwrap(), "assets/app.js");
+        assert_eq!(decoded("/assets%2Fapp.js").unwrap(), "assets/app.js");
+        assert_eq!(decoded("/").unwrap(), "");
+        for uri_path in ["/../secrets.toml", "/assets/%2e%2e/%2e%2e/x", "/%ff.js"] {
+            assert_eq!(decoded(uri_path), None, "{uri_path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn encoded_paths_get_the_headers_of_their_file() {
+        let url = serve_ui("encoded", &[SECRETS]).await;
+        let response = get(&format!("{url}assets/a%20b.js"), &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // Not the rule and the version of `index.html`
+        assert_eq!(response.headers()[header::CACHE_CONTROL], IMMUTABLE);
+        let etag = response.headers()[header::ETAG].to_str().unwrap();
+        let version_of_size = format!("\"{:x}-", SPACED_JS.len());
+        assert!(etag.starts_with(&version_of_size), "{etag}");
+        assert_eq!(response.text().await.unwrap(), SPACED_JS);
+    }
+
+    #[tokio::test]
+    async fn cache_rules_come_from_the_configuration() {
+        let config = [SECRETS, ("SPA_CACHE_RULES", "assets/**=max-age=60")];
+        let url = serve_ui("rules", &config).await;
+        let response = get(&format!("{url}assets/app.js"), &[]).await;
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "max-age=60");
+
+        // The rendered `index.html` is never cached, whatever the rules say.
+        let response = get(&format!("{url}index.html"), &[]).await;
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+    }
 }
@@ -0,0 +1,242 @@
+//! Serves the UI from memory, with the `embed-ui` feature. `ui/dist` is embedded at compile time,
+//! so build the UI first (`make build_ui`).
+//!
+//! Like `ServeDir`, this serves the `.br` and `.gz` siblings of a file to clients that accept
+//! them.
+
+use ::axum::body::Body;
+use ::axum::extract::{Request, State};
+use ::axum::http::{header, HeaderValue, Method, StatusCode};
+use ::axum::response::{IntoResponse, Response};
+use ::include_dir::{Dir, File};
+use ::std::collections::hash_map::DefaultHasher;
+use ::std::collections::HashMap;
+use ::std::hash::{Hash, Hasher};
+use ::std::path::{Path, PathBuf};
+use ::std::sync::OnceLock;
+
+use super::index_html::{self, IndexHtml};
+
+#[cfg(all(not(test), not(ui_dist_missing)))]
+static UI_DIST: Dir<'static> = ::include_dir::include_dir!("$CARGO_MANIFEST_DIR/../ui/dist");
+/// A debug build without a built UI embeds none (see `build.rs`).
+#[cfg(all(not(test), ui_dist_missing))]
+static UI_DIST: Dir<'static> = Dir::new("", &[]);
+#[cfg(test)]
+static UI_DIST: Dir<'static> =
+    ::include_dir::include_dir!("$CARGO_MANIFEST_DIR/tests/fixtures/ui_dist");
+
+/// Precompressed siblings, in order of preference
+const ENCODINGS: &[(&str, &str)] = &[("br", "br"), ("gzip", "gz")];
+
+pub(super) fn file(relative_path: &Path) -> Option<&'static File<'static>> {
+    UI_DIST.get_file(relative_path)
+}
+
+/// The hashes of all embedded files, computed once.
+pub(super) fn hash(relative_path: &Path) -> Option<u64> {
+    static HASHES: OnceLock<HashMap<PathBuf, u64>> = OnceLock::new();
+    HASHES
+        .get_or_init(|| {
+            let mut hashes = HashMap::new();
+            collect_hashes(&UI_DIST, &mut hashes);
+            hashes
+        })
+        .get(relative_path)
+        .copied()
+}
+
+fn collect_hashes(dir: &Dir<'static>, hashes: &mut HashMap<PathBuf, u64>) {
+    for file in dir.files() {
+        let mut hasher = DefaultHasher::new();
+        file.contents().hash(&mut hasher);
+        hashes.insert(file.path().to_path_buf(), hasher.finish());
+    }
+    for dir in dir.dirs() {
+        collect_hashes(dir, hashes);
+    }
+}
+
+/// Serves an embedded file, or else the SPA fallback.
+pub(super) async fn serve(State(index_html): State<IndexHtml>, request: Request) -> Response {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return StatusCode::METHOD_NOT_ALLOWED.into_response();
+    }
+    let relative_path = super::decoded_path(request.uri().path()).unwrap_or_default();
+    let Some(original) = file(&relative_path).filter(|_| !request.uri().path().ends_with('/'))
+    else {
+        return if super::is_navigation(&request) {
+            index_html::serve(State(index_html), request).await
+        } else {
+            StatusCode::NOT_FOUND.into_response()
+        };
+    };
+
+    let content_type = ::mime_guess::from_path(&relative_path).first_or_octet_stream();
+    let (encoding, contents) = ENCODINGS
+        .iter()
+        .filter(|(encoding, _)| accepts_encoding(&request, encoding))
+        .find_map(|(encoding, extension)| {
+            let mut sibling = relative_path.clone().into_os_string();
+            sibling.push(format!(".{extension}"));
+            file(Path::new(&sibling)).map(|file| (Some(*encoding), file.contents()))
+        })
+        .unwrap_or((None, original.contents()));
+
+    let mut response = Body::from(contents).into_response();
+    let headers = response.headers_mut();
+    // A `mime` always is a valid header value.
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::try_from(content_type.as_ref()).unwrap(),
+    );
+    if let Some(encoding) = encoding {
+        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+    }
+    response
+}
+
+/// Whether `Accept-Encoding` lists `encoding`, without `q=0`.
+fn accepts_encoding(request: &Request, encoding: &str) -> bool {
+    request
+        .headers()
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            parts.next().is_some_and(|name| name == encoding)
+                && !parts.any(|parameter| {
+                    parameter
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .is_some_and(|q| q == 0.0)
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use ::axum::http::HeaderName;
+
+    use super::*;
+    use crate::config::SharedConfig;
+    use crate::route::spa::{self, UiFiles};
+    use crate::test_support;
+
+    fn config() -> SharedConfig {
+        SharedConfig::for_tests(&[("GITHUB_APP_CLIENT_SECRET", "secret")])
+    }
+
+    async fn request(method: Method, path: &str, headers: &[(HeaderName, &str)]) -> Response {
+        let mut request = Request::builder().method(method).uri(path);
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        serve(
+            State(IndexHtml::new(UiFiles::Embedded, config())),
+            request.body(Body::empty()).unwrap(),
+        )
+        .await
+    }
+
+    async fn text(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn precompressed_files_are_served_to_clients_that_accept_them() {
+        for (path, accept_encoding, expected) in [
+            ("/assets/app.js", "", None),
+            ("/assets/app.js", "gzip, deflate, br", Some("br")),
+            ("/assets/app.js", "gzip", Some("gzip")),
+            ("/assets/app.js", "br;q=0.5", Some("br")),
+            ("/assets/app.js", "br;q=0, gzip", Some("gzip")),
+            ("/assets/app.js", "br; q=0, gzip;q=0", None),
+            ("/assets/app.js", "deflate", None),
+            // Without a `.br` sibling, the next encoding is served.
+            ("/assets/app.css", "br, gzip", Some("gzip")),
+        ] {
+            let headers = [(header::ACCEPT_ENCODING, accept_encoding)];
+            let response = request(Method::GET, path, &headers).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let content_encoding = response
+                .headers()
+                .get(header::CONTENT_ENCODING)
+                .map(|value| value.to_str().unwrap().to_string());
+            assert_eq!(
+                content_encoding.as_deref(),
+                expected,
+                "{path} with {accept_encoding:?}"
+            );
+            // The type is the one of the original file, whatever the encoding.
+            let content_type = response.headers()[header::CONTENT_TYPE].clone();
+            let contents = text(response).await;
+            match expected {
+                None if path.ends_with(".js") => {
+                    assert_eq!(content_type, "application/javascript");
+                    assert_eq!(contents, "console.log(1)\n");
+                }
+                None => assert_eq!(content_type, "text/css"),
+                Some("br") => assert_eq!(contents, "brotli\n"),
+                Some(_) => assert_eq!(contents, "gzip\n"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_paths_fall_back_to_index_html_for_navigations_only() {
+        for path in ["/", "/repositories/42", "/assets/"] {
+            let response = request(Method::GET, path, &[]).await;
+            assert_eq!(response.status(), StatusCode::OK, "{path}");
+            let html = text(response).await;
+            assert!(html.contains("id=\"app-config\""), "{path}: {html}");
+            assert!(html.contains("Embedded"), "{path}: {html}");
+        }
+        let headers = [(header::ACCEPT, "text/html")];
+        let response = request(Method::GET, "/report.pdf", &headers).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = request(Method::GET, "/assets/missing.js", &[]).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = request(Method::POST, "/assets/app.js", &[]).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let response = request(Method::HEAD, "/assets/app.js", &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn embedded_files_have_an_etag_per_encoding() {
+        let router = spa::router(UiFiles::Embedded, config());
+        let url = format!("http://{}/assets/app.js", test_support::serve(router).await);
+        let client = reqwest::Client::new();
+        let get = |accept_encoding: &'static str| {
+            client
+                .get(&url)
+                .header(header::ACCEPT_ENCODING, accept_encoding)
+                .send()
+        };
+
+        let identity = get("identity").await.unwrap();
+        let brotli = get("br").await.unwrap();
+        assert_eq!(brotli.headers()[header::CONTENT_ENCODING], "br");
+        let etag = identity.headers()[header::ETAG].to_str().unwrap();
+        let brotli_etag = brotli.headers()[header::ETAG].to_str().unwrap();
+        let version = hash(Path::new("assets/app.js")).unwrap();
+        assert_eq!(etag, format!("\"{version:x}\""));
+        assert_eq!(brotli_etag, format!("\"{version:x}-br\""));
+
+        let response = client
+            .get(&url)
+            .header(header::ACCEPT_ENCODING, "br")
+            .header(header::IF_NONE_MATCH, brotli_etag)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    }
+}
@@ -0,0 +1,200 @@
+//! Serves `index.html` with the runtime configuration of the UI, so that one build of `ui/dist`
+//! can be promoted from staging to production unchanged.
+//!
+//! The configuration is injected as a JSON data block, which `ui/src/interop.js` passes on to
+//! the Elm app:
+//!
+//! ```html
+//! <script id="app-config" type="application/json" nonce="…">{"environment":"production",…}</script>
+//! ```
+//!
+//! Every `<script>` element gets the nonce of the request (see `crate::security`), so that the
+//! `Content-Security-Policy` allows exactly these scripts. The configuration includes the CSRF
+//! token of the browser session (see `crate::csrf`).
+
+use ::anyhow::Context;
+use ::axum::extract::{Request, State};
+use ::axum::http::{header, HeaderMap, StatusCode};
+use ::axum::response::{Html, IntoResponse, Response};
+use ::serde::Serialize;
+use ::std::collections::BTreeMap;
+use ::std::path::Path;
+use ::tracing::error;
+
+use super::UiFiles;
+use crate::config::{Config, SharedConfig};
+use crate::csrf::CsrfToken;
+use crate::security::CspNonce;
+
+/// What the UI needs to know about the deployment it runs in.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UiConfig<'a> {
+    environment: &'a str,
+    api_base_url: &'a str,
+    github_client_id: &'a str,
+    /// All enabled features, mapped to `true`.
+    features: BTreeMap<&'a str, bool>,
+    version: &'static str,
+    git_commit: &'static str,
+    /// For the `X-CSRF-Token` header of unsafe requests to the API
+    csrf_token: &'a str,
+}
+
+impl<'a> UiConfig<'a> {
+    fn new(config: &'a Config, csrf_token: &'a CsrfToken) -> Self {
+        UiConfig {
+            environment: &config.environment,
+            api_base_url: &config.api_base_url,
+            github_client_id: &config.github_app_client_id,
+            features: config
+                .feature_flags
+                .iter()
+                .map(|flag| (flag.as_str(), true))
+                .collect(),
+            version: env!("CARGO_PKG_VERSION"),
+            git_commit: env!("GIT_COMMIT"),
+            csrf_token: &csrf_token.0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct IndexHtml {
+    ui_files: UiFiles,
+    /// Read for every request, so that a reloaded configuration takes effect immediately
+    config: SharedConfig,
+}
+
+impl IndexHtml {
+    pub(super) fn new(ui_files: UiFiles, config: SharedConfig) -> Self {
+        IndexHtml { ui_files, config }
+    }
+
+    fn render(
+        &self,
+        template: &str,
+        nonce: &CspNonce,
+        csrf_token: &CsrfToken,
+    ) -> anyhow::Result<String> {
+        let CspNonce(nonce) = nonce;
+        let config = self.config.current();
+        let config_json = serde_json::to_string(&UiConfig::new(&config, csrf_token))
+            .context("Cannot serialize the UI configuration")?
+            // `</script>` or `<!--` in a string must not end the `<script>` element.
+            .replace('<', "\\u003c");
+        let html = template.replace("<script", &format!("<script nonce=\"{nonce}\""));
+        let config_script = format!(
+            "<script id=\"app-config\" type=\"application/json\" nonce=\"{nonce}\">{config_json}</script>"
+        );
+        Ok(match html.find("</head>") {
+            Some(end_of_head) => {
+                let (head, rest) = html.split_at(end_of_head);
+                format!("{head}{config_script}{rest}")
+            }
+            None => format!("{config_script}{html}"),
+        })
+    }
+}
+
+/// The file is read for every request, so that rebuilding the UI takes effect without a restart.
+pub(super) async fn serve(State(index_html): State<IndexHtml>, request: Request) -> Response {
+    let template = match index_html
+        .ui_files
+        .read_to_string(Path::new("index.html"))
+        .await
+    {
+        Ok(template) => template,
+        Err(e) => {
+            error!("Cannot read index.html from {}: {e}", index_html.ui_files);
+            return StatusCode::NOT_FOUND.into_response();
+        }
+    };
+    let nonce = request
+        .extensions()
+        .get::<CspNonce>()
+        .cloned()
+        .unwrap_or_else(CspNonce::new);
+    let (csrf_token, set_cookie) = CsrfToken::for_request(request.headers());
+    let html = match index_html.render(&template, &nonce, &csrf_token) {
+        Ok(html) => html,
+        Err(e) => {
+            error!("{e:#}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    (
+        // Each response has its own nonce. A cached copy would not match the nonce in the
+        // `Content-Security-Policy` of a revalidation.
+        [(header::CACHE_CONTROL, "no-store")],
+        set_cookie.into_iter().collect::<HeaderMap>(),
+        Html(html),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use ::std::path::PathBuf;
+
+    use super::*;
+
+    const NONCE: &str = "bm9uY2U";
+
+    fn render(template: &str, config: &[(&str, &str)]) -> String {
+        let config = [&[("GITHUB_APP_CLIENT_SECRET", "secret")], config].concat();
+        let index_html = IndexHtml::new(
+            UiFiles::Directory(PathBuf::from("ui/dist")),
+            SharedConfig::for_tests(&config),
+        );
+        let nonce = CspNonce(NONCE.to_string());
+        let csrf_token = CsrfToken("csrf".to_string());
+        index_html.render(template, &nonce, &csrf_token).unwrap()
+    }
+
+    /// The JSON in the data block of the configuration
+    fn config_json(html: &str) -> &str {
+        let start = html.find("\">{").unwrap() + 2;
+        let end = start + html[start..].find("</script>").unwrap();
+        &html[start..end]
+    }
+
+    #[test]
+    fn every_script_gets_the_nonce() {
+        let template = "<html><head><script src=\"/a.js\"></script><script type=\"module\">\
+                        import '/b.js'</script></head><body><script>c()</script></body></html>";
+        let html = render(template, &[]);
+        let nonce = format!("<script nonce=\"{NONCE}\"");
+        assert_eq!(html.matches("<script").count(), 4);
+        assert_eq!(html.matches(&nonce).count(), 3);
+        assert!(html.contains(&format!(
+            "<script id=\"app-config\" type=\"application/json\" nonce=\"{NONCE}\">"
+        )));
+        // The configuration comes last in the head, after the scripts of the template.
+        let config = html.find("app-config").unwrap();
+        assert!(html.find("/b.js").unwrap() < config && config < html.find("</head>").unwrap());
+    }
+
+    #[test]
+    fn the_configuration_cannot_end_its_script() {
+        let environment = "</script><script>alert(1)</script><!--";
+        let html = render("<head></head>", &[("ENVIRONMENT", environment)]);
+        assert_eq!(html.matches("</script>").count(), 1);
+        assert!(!html.contains("<!--"));
+        let config: serde_json::Value = serde_json::from_str(config_json(&html)).unwrap();
+        assert_eq!(config["environment"], environment);
+        assert_eq!(config["csrfToken"], "csrf");
+    }
+
+    #[test]
+    fn templates_without_a_head_get_the_configuration_first() {
+        let html = render("<p>No head</p>", &[("FEATURE_FLAGS", "a,b")]);
+        assert!(html.starts_with("<script id=\"app-config\""));
+        assert!(html.ends_with("</script><p>No head</p>"));
+        let config: serde_json::Value = serde_json::from_str(config_json(&html)).unwrap();
+        assert_eq!(
+            config["features"],
+            serde_json::json!({ "a": true, "b": true })
+        );
+    }
+}
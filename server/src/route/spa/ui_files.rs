@@ -0,0 +1,78 @@
+use ::std::fmt;
+use ::std::io;
+use ::std::path::{Component, Path, PathBuf};
+use ::std::time::UNIX_EPOCH;
+
+#[cfg(feature = "embed-ui")]
+use super::embedded;
+
+/// Where the files of the built UI come from.
+#[derive(Clone, Debug)]
+pub(crate) enum UiFiles {
+    /// A directory on disk, usually `ui/dist`. Shuttle deploys it as an asset (see `Shuttle.toml`).
+    #[cfg_attr(feature = "embed-ui", allow(dead_code))]
+    Directory(PathBuf),
+    /// `ui/dist` as it was at compile time, embedded into the binary with the `embed-ui` feature
+    #[cfg(feature = "embed-ui")]
+    Embedded,
+}
+
+impl UiFiles {
+    /// `relative_path` is relative to the root of the UI, e.g. `assets/index-1a2b3c.js`.
+    pub(crate) fn is_file(&self, relative_path: &Path) -> bool {
+        if !is_normal(relative_path) {
+            return false;
+        }
+        match self {
+            UiFiles::Directory(directory_path) => directory_path.join(relative_path).is_file(),
+            #[cfg(feature = "embed-ui")]
+            UiFiles::Embedded => embedded::file(relative_path).is_some(),
+        }
+    }
+
+    pub(crate) async fn read_to_string(&self, relative_path: &Path) -> io::Result<String> {
+        match self {
+            UiFiles::Directory(directory_path) => {
+                tokio::fs::read_to_string(directory_path.join(relative_path)).await
+            }
+            #[cfg(feature = "embed-ui")]
+            UiFiles::Embedded => embedded::file(relative_path)
+                .ok_or_else(|| io::ErrorKind::NotFound.into())
+                .and_then(|file| {
+                    String::from_utf8(file.contents().to_vec())
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+                }),
+        }
+    }
+
+    /// Changes whenever the contents of the file change: the size and modification time of a
+    /// file on disk, or the hash of an embedded file.
+    pub(crate) fn version(&self, relative_path: &Path) -> Option<String> {
+        match self {
+            UiFiles::Directory(directory_path) => {
+                let metadata = directory_path.join(relative_path).metadata().ok()?;
+                let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+                Some(format!("{:x}-{:x}", metadata.len(), modified.as_nanos()))
+            }
+            #[cfg(feature = "embed-ui")]
+            UiFiles::Embedded => embedded::hash(relative_path).map(|hash| format!("{hash:x}")),
+        }
+    }
+}
+
+impl fmt::Display for UiFiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UiFiles::Directory(directory_path) => write!(f, "{}", directory_path.display()),
+            #[cfg(feature = "embed-ui")]
+            UiFiles::Embedded => f.write_str("the embedded UI"),
+        }
+    }
+}
+
+/// Rejects `..`, `/` and the like, which could escape the UI.
+fn is_normal(relative_path: &Path) -> bool {
+    relative_path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+}
@@ -0,0 +1,213 @@
+//! Security headers: `Content-Security-Policy`, `Strict-Transport-Security` and friends.
+//!
+//! Each area of the server gets the policy that fits its responses: the SPA needs scripts and
+//! styles, while `/api` and `/oauth` only return data and redirects. Apply the most specific
+//! policy first: headers that are already set are left alone.
+
+use ::axum::extract::{Request, State};
+use ::axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+use ::axum::middleware::Next;
+use ::axum::response::Response;
+use ::reqwest::Url;
+use ::std::time::Duration;
+
+use crate::config::SharedConfig;
+
+/// Where browsers send reports of CSP violations.
+const CSP_REPORT_PATH: &str = "/api/csp-report";
+
+const PERMISSIONS_POLICY: &str = "camera=(), geolocation=(), microphone=(), payment=(), usb=()";
+
+/// A random value that marks the scripts of one response as trusted. [`set_headers`] stores it
+/// in the request extensions, so that the policy and the rendered `index.html` use the same one.
+#[derive(Clone, Debug)]
+pub(crate) struct CspNonce(pub String);
+
+impl CspNonce {
+    pub(crate) fn new() -> Self {
+        CspNonce(format!("{:032x}", ::rand::random::<u128>()))
+    }
+}
+
+/// The parts of the server, with different needs.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Area {
+    /// The single page app: HTML, scripts, styles
+    Spa,
+    /// JSON and event streams, and everything else that is not a page
+    Api,
+    /// The OAuth callback, whose URL carries the authorization code
+    OAuth,
+}
+
+/// Settings of all policies.
+#[derive(Clone, Debug)]
+pub(crate) struct SecurityConfig {
+    /// Only report CSP violations, instead of blocking. For trying out a stricter policy.
+    pub csp_report_only: bool,
+    /// How long browsers must only use HTTPS. `None` sends no `Strict-Transport-Security`.
+    pub hsts_max_age: Option<Duration>,
+}
+
+/// The policy of an [`Area`]. Use it with [`axum::middleware::from_fn_with_state`] and
+/// [`set_headers`].
+#[derive(Clone)]
+pub(crate) struct SecurityHeaders {
+    area: Area,
+    config: SecurityConfig,
+    /// Read for every request, so that the UI may connect to a reloaded `API_BASE_URL` at once
+    shared_config: SharedConfig,
+}
+
+impl SecurityHeaders {
+    pub(crate) fn new(area: Area, config: SecurityConfig, shared_config: SharedConfig) -> Self {
+        Self {
+            area,
+            config,
+            shared_config,
+        }
+    }
+
+    fn content_security_policy(&self, nonce: Option<&CspNonce>) -> String {
+        let directives = match (self.area, nonce) {
+            (Area::Spa, Some(CspNonce(nonce))) => format!(
+                "default-src 'self'; \
+                 script-src 'self' 'nonce-{nonce}'; \
+                 style-src 'self' 'unsafe-inline'; \
+                 img-src 'self' data: https://avatars.githubusercontent.com; \
+                 connect-src {}; \
+                 form-action 'self' https://github.com; \
+                 base-uri 'self'; \
+                 object-src 'none'; \
+                 frame-ancestors 'none'",
+                self.connect_sources()
+            ),
+            _ => "default-src 'none'; frame-ancestors 'none'".to_string(),
+        };
+        format!("{directives}; report-uri {CSP_REPORT_PATH}; report-to csp")
+    }
+
+    /// The UI calls its API and the GitHub API. An API on another origin must be allowed, too.
+    /// (In a `connect-src`, an `https:` origin also allows its `wss:` WebSocket.)
+    fn connect_sources(&self) -> String {
+        let api_origin = Url::parse(&self.shared_config.current().api_base_url)
+            .ok()
+            .map(|url| url.origin())
+            .filter(|origin| origin.is_tuple())
+            .map(|origin| format!(" {}", origin.ascii_serialization()));
+        format!(
+            "'self' https://api.github.com{}",
+            api_origin.unwrap_or_default()
+        )
+    }
+
+    fn referrer_policy(&self) -> &'static str {
+        match self.area {
+            Area::Spa | Area::Api => "strict-origin-when-cross-origin",
+            // The authorization code must not leak to the next page.
+            Area::OAuth => "no-referrer",
+        }
+    }
+}
+
+pub(crate) async fn set_headers(
+    State(policy): State<SecurityHeaders>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let nonce = matches!(policy.area, Area::Spa).then(CspNonce::new);
+    if let Some(nonce) = &nonce {
+        request.extensions_mut().insert(nonce.clone());
+    }
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+
+    let csp_header = if policy.config.csp_report_only {
+        header::CONTENT_SECURITY_POLICY_REPORT_ONLY
+    } else {
+        header::CONTENT_SECURITY_POLICY
+    };
+    if !headers.contains_key(header::CONTENT_SECURITY_POLICY)
+        && !headers.contains_key(header::CONTENT_SECURITY_POLICY_REPORT_ONLY)
+    {
+        insert(
+            headers,
+            csp_header,
+            policy.content_security_policy(nonce.as_ref()),
+        );
+    }
+    insert_if_absent(
+        headers,
+        HeaderName::from_static("reporting-endpoints"),
+        format!("csp=\"{CSP_REPORT_PATH}\""),
+    );
+    if let Some(max_age) = policy.config.hsts_max_age {
+        insert_if_absent(
+            headers,
+            header::STRICT_TRANSPORT_SECURITY,
+            format!("max-age={}; includeSubDomains", max_age.as_secs()),
+        );
+    }
+    insert_if_absent(
+        headers,
+        header::X_CONTENT_TYPE_OPTIONS,
+        "nosniff".to_string(),
+    );
+    // For browsers that do not know `frame-ancestors`
+    insert_if_absent(headers, header::X_FRAME_OPTIONS, "DENY".to_string());
+    insert_if_absent(
+        headers,
+        header::REFERRER_POLICY,
+        policy.referrer_policy().to_string(),
+    );
+    insert_if_absent(
+        headers,
+        HeaderName::from_static("permissions-policy"),
+        PERMISSIONS_POLICY.to_string(),
+    );
+    response
+}
+
+fn insert_if_absent(headers: &mut HeaderMap, name: HeaderName, value: String) {
+    if !headers.contains_key(&name) {
+        insert(headers, name, value);
+    }
+}
+
+fn insert(headers: &mut HeaderMap, name: HeaderName, value: String) {
+    // All values are built from constants and hex digits.
+    headers.insert(name, HeaderValue::try_from(value).unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connect_src(api_base_url: &str) -> String {
+        let config = SharedConfig::for_tests(&[
+            ("GITHUB_APP_CLIENT_SECRET", "secret"),
+            ("API_BASE_URL", api_base_url),
+        ]);
+        let security_config = config.current().security.clone();
+        let policy = SecurityHeaders::new(Area::Spa, security_config, config);
+        let csp = policy.content_security_policy(Some(&CspNonce::new()));
+        let directive = csp
+            .split("; ")
+            .find(|directive| directive.starts_with("connect-src "))
+            .unwrap();
+        directive["connect-src ".len()..].to_string()
+    }
+
+    #[test]
+    fn the_ui_may_connect_to_its_api() {
+        assert_eq!(connect_src("/api"), "'self' https://api.github.com");
+        assert_eq!(
+            connect_src("https://api.example.com/v1/"),
+            "'self' https://api.github.com https://api.example.com"
+        );
+        assert_eq!(
+            connect_src("http://localhost:8000/api"),
+            "'self' https://api.github.com http://localhost:8000"
+        );
+    }
+}
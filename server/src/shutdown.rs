@@ -0,0 +1,56 @@
+//! Graceful shutdown. On `SIGINT` or `SIGTERM`, the server stops accepting connections and gives
+//! the requests in flight and the WebSocket sessions [`DRAIN_DEADLINE`] to complete. Long-lived
+//! responses end right away: WebSocket sessions get a close frame with code 1001 ("going away"),
+//! and SSE streams end, so that clients reconnect, to another instance if there is one.
+//!
+//! This applies to the standalone server and, for the signals it gets, to the Shuttle service. A
+//! stop requested through the Shuttle runtime aborts the service though, without draining.
+
+use ::axum::Router;
+use ::std::io;
+use ::std::net::SocketAddr;
+use ::std::time::Duration;
+use ::tokio::net::TcpListener;
+use ::tracing::{info, warn};
+
+pub(crate) use ::common::shutdown::Shutdown;
+
+/// Below the 30 seconds that container runtimes wait after `SIGTERM` before they kill the process,
+/// so that the server gets to log how the shutdown went.
+pub(crate) const DRAIN_DEADLINE: Duration = Duration::from_secs(25);
+
+/// Serves `router` until `shutdown` is triggered, and then until the requests in flight and the
+/// WebSocket sessions have completed, or [`DRAIN_DEADLINE`] has passed.
+pub(crate) async fn serve(
+    listener: TcpListener,
+    router: Router,
+    shutdown: Shutdown,
+) -> io::Result<()> {
+    info!("Listening on {}", listener.local_addr()?);
+    // The rate limiter falls back to the peer address when there is no trusted proxy.
+    let server = ::axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown({
+        let shutdown = shutdown.clone();
+        async move { shutdown.triggered().await }
+    });
+    // Upgraded connections are not awaited by the server, see `common::shutdown`.
+    let drained = async {
+        server.await?;
+        shutdown.sessions_ended().await;
+        Ok::<_, io::Error>(())
+    };
+    ::tokio::select! {
+        result = drained => {
+            result?;
+            info!("Shut down");
+        }
+        () = shutdown.deadline(DRAIN_DEADLINE) => warn!(
+            "Requests still in flight after {}s, shutting down anyway",
+            DRAIN_DEADLINE.as_secs()
+        ),
+    }
+    Ok(())
+}
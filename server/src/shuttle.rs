@@ -0,0 +1,32 @@
+//! Runs the server on Shuttle. Unlike the `AxumService` of `shuttle-axum`, the service shuts down
+//! gracefully on `SIGTERM` and Ctrl+C, as the standalone server does (see `crate::shutdown`).
+
+use ::axum::Router;
+use ::common::tracing::OtlpExport;
+use ::shuttle_runtime::{CustomError, Error, Service};
+use ::std::net::SocketAddr;
+use ::tokio::net::TcpListener;
+
+use crate::shutdown::{self, Shutdown};
+
+pub(crate) type ShuttleServer = Result<ShuttleService, Error>;
+
+pub(crate) struct ShuttleService {
+    pub router: Router,
+    /// Triggered by the signals that the process gets
+    pub shutdown: Shutdown,
+    pub otlp_export: Option<OtlpExport>,
+}
+
+#[::shuttle_runtime::async_trait]
+impl Service for ShuttleService {
+    async fn bind(self, address: SocketAddr) -> Result<(), Error> {
+        let listener = TcpListener::bind(address).await.map_err(CustomError::new)?;
+        let served = shutdown::serve(listener, self.router, self.shutdown).await;
+        if let Some(otlp_export) = self.otlp_export {
+            otlp_export.shutdown().await;
+        }
+        served.map_err(CustomError::new)?;
+        Ok(())
+    }
+}
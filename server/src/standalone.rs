@@ -0,0 +1,48 @@
+//! Runs the server with plain tokio and axum, without the Shuttle runtime (`standalone` feature):
+//!
+//! ```sh
+//! cargo run -p server --features standalone
+//! ```
+//!
+//! It is configured by environment variables:
+//!
+//! - `BIND_ADDRESS`: where to listen, `127.0.0.1:8000` by default, where `dev_server` expects the
+//!   server. Containers need `0.0.0.0:8000`.
+//! - `SECRETS_FILE`: a TOML file like `server/Secrets.toml`, which is the default if it exists.
+//!   It takes the place of the Shuttle secret store (see `crate::config`).
+//!
+//! `--print-config` prints the configuration, with secrets masked, instead of starting the
+//! server. The server shuts down gracefully on `SIGTERM` and Ctrl+C, see `crate::shutdown`.
+
+use ::anyhow::{Context, Result};
+use ::axum::Router;
+use ::std::env;
+use ::std::net::SocketAddr;
+use ::tokio::net::TcpListener;
+use ::tracing::info;
+
+use crate::config::Layer;
+use crate::shutdown::{self, Shutdown};
+
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:8000";
+
+/// Takes the place of the Shuttle secret store.
+pub(crate) fn secrets_file() -> Result<Option<Layer>> {
+    let secrets_file = Layer::file_from_env("SECRETS_FILE", "server/Secrets.toml")?;
+    if secrets_file.is_none() {
+        info!("No secrets file, reading secrets from environment variables only");
+    }
+    Ok(secrets_file)
+}
+
+/// Listens on `BIND_ADDRESS`, see [`shutdown::serve`].
+pub(crate) async fn serve(router: Router, shutdown: Shutdown) -> Result<()> {
+    let bind_address = env::var("BIND_ADDRESS").unwrap_or_else(|_| DEFAULT_BIND_ADDRESS.into());
+    let bind_address: SocketAddr = bind_address
+        .parse()
+        .with_context(|| format!("BIND_ADDRESS {bind_address} is invalid"))?;
+    let listener = TcpListener::bind(bind_address)
+        .await
+        .with_context(|| format!("Cannot listen on {bind_address}"))?;
+    Ok(shutdown::serve(listener, router, shutdown).await?)
+}
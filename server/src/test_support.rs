@@ -0,0 +1,15 @@
+//! Fixtures shared by the tests of several modules.
+
+use ::axum::Router;
+use ::std::net::SocketAddr;
+use ::tokio::net::TcpListener;
+
+/// Serves `router` on a free port of the loopback interface, and returns its address. Handlers
+/// get the peer address as `ConnectInfo`, as with the standalone server.
+pub(crate) async fn serve(router: Router) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let service = router.into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, service).await.unwrap() });
+    address
+}
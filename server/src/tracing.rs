@@ -1,20 +1,110 @@
+use ::axum::extract::MatchedPath;
+use ::axum::http::{self, HeaderMap};
 use ::axum::Router;
-use ::axum::{extract::MatchedPath, http};
+use ::common::tracing::{otlp_layer, HeaderExtractor, HeaderInjector, LogFilter, OtlpExport};
+use ::opentelemetry::global;
+use ::opentelemetry_sdk::propagation::TraceContextPropagator;
+use ::std::time::Duration;
+use ::tower_http::classify::ServerErrorsFailureClass;
+use ::tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use ::tower_http::trace::TraceLayer;
-use ::tracing::info_span;
+use ::tracing::{error, info, info_span, warn, Span};
+use ::tracing_opentelemetry::OpenTelemetrySpanExt;
+use ::tracing_subscriber::EnvFilter;
 use ::tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-pub(crate) fn init() {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                // axum logs rejections from built-in extractors with the `axum::rejection`
-                // target, at `TRACE` level. `axum::rejection=trace` enables showing those events
-                "server=debug,tower_http=debug,axum::rejection=trace".into()
-            }),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+use crate::redact::ScrubbingSubscriber;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The directives of the log filter, unless `RUST_LOG` is set.
+///
+/// axum logs rejections from built-in extractors with the `axum::rejection` target, at `TRACE`
+/// level. `axum::rejection=trace` enables showing those events. Events reported by the UI have the
+/// target `client`, and the ones of the code shared with the proxy the target `common`.
+const DEFAULT_LOG_FILTER: &str =
+    "server=debug,client=info,common=info,tower_http=debug,axum::rejection=trace";
+
+/// Reported to the OpenTelemetry collector, unless `OTEL_SERVICE_NAME` is set.
+const DEFAULT_SERVICE_NAME: &str = "elm-on-shuttle-server";
+
+/// Returns the log filter, and the OTLP export to shut down once the server has stopped.
+pub(crate) fn init() -> (LogFilter, Option<OtlpExport>) {
+    // W3C trace context (`traceparent` and `tracestate` headers) is propagated even if spans are
+    // not exported, so that the traces of the services around us stay connected.
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let ((otlp_layer, otlp_export), otlp_error) = match otlp_layer(DEFAULT_SERVICE_NAME) {
+        Ok(otlp) => (otlp.unzip(), None),
+        Err(e) => ((None, None), Some(e)),
+    };
+    let is_exporting = otlp_layer.is_some();
+    let log_format = LogFormat::from_env();
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| DEFAULT_LOG_FILTER.into());
+    let (filter, log_filter) = LogFilter::new(filter);
+
+    // Scrubbing comes first, so that no layer gets to see the secrets.
+    ScrubbingSubscriber(
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(log_format.is_json().then(|| {
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(false)
+            }))
+            .with((!log_format.is_json()).then(tracing_subscriber::fmt::layer))
+            .with(otlp_layer),
+    )
+    .init();
+
+    if is_exporting {
+        info!("Exporting traces with OTLP");
+    }
+    if let Some(e) = otlp_error {
+        warn!("Cannot export traces with OTLP: {e:#}");
+    }
+    if let LogFormat::Unknown(format) = log_format {
+        warn!("Unknown LOG_FORMAT `{format}`, using `text`");
+    }
+    (log_filter, otlp_export)
+}
+
+/// How log events are written, as selected with the `LOG_FORMAT` environment variable.
+enum LogFormat {
+    /// Human-readable lines (the default)
+    Text,
+    /// One JSON object per line, for log aggregators
+    Json,
+    Unknown(String),
+}
+
+impl LogFormat {
+    fn from_env() -> Self {
+        match std::env::var("LOG_FORMAT") {
+            Err(_) => LogFormat::Text,
+            Ok(format) => match format.as_str() {
+                "text" => LogFormat::Text,
+                "json" => LogFormat::Json,
+                _ => LogFormat::Unknown(format),
+            },
+        }
+    }
+
+    fn is_json(&self) -> bool {
+        matches!(self, LogFormat::Json)
+    }
+}
+
+/// Returns headers that pass the context of `span` on to a service we call, so that its spans
+/// become part of our trace.
+pub(crate) fn trace_context_headers(span: &Span) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let context = span.context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
 }
 
 pub(crate) fn wrap_router(router: Router) -> Router {
@@ -28,50 +118,170 @@ pub(crate) fn wrap_router(router: Router) -> Router {
     //
     // See https://docs.rs/tower-http/0.1.1/tower_http/trace/index.html for more details.
     //
-    // If you want to customize the behavior using closures here is how.
-    router.layer(
-        TraceLayer::new_for_http().make_span_with(|request: &http::Request<_>| {
-            // Log the matched route's path (with placeholders not filled in).
-            // Use request.uri() or OriginalUri if you want the real path.
-            let path = request.uri().path();
-            let matched_path = request
-                .extensions()
-                .get::<MatchedPath>()
-                .map(MatchedPath::as_str)
-                .filter(|&matched_path| matched_path != path);
-            info_span!(
-                "http_request",
-                method = ?request.method(),
-                matched_path,
-                path,
-                some_other_field = tracing::field::Empty,
-            )
-        }),
-        // use std::time::Duration;
-        // use tower_http::classify::ServerErrorsFailureClass;
-        // use tracing::Span;
-        // .on_request(|_request: &Request<_>, _span: &Span| {
-        //     // You can use `_span.record("some_other_field", value)` in one of these
-        //     // closures to attach a value to the initially empty field in the info_span
-        //     // created above.
-        // })
-        // .on_response(
-        //     |_response: &http::Response, _latency: Duration, _span: &Span| {
-        //         // ...
-        //     },
-        // )
-        // .on_body_chunk(|_chunk: &Bytes, _latency: Duration, _span: &Span| {
-        //     // ...
-        // })
-        // .on_eos(
-        //     |_trailers: Option<&HeaderMap>, _stream_duration: Duration, _span: &Span| {
-        //         // ...
-        //     },
-        // )
-        // .on_failure(
-        //     |_error: ServerErrorsFailureClass, _latency: Duration, _span: &Span| {
-        //         // ...
-        //     },
-        // ),
-    )
+    // The request ID is set (unless the client or a proxy has sent one) before the span is
+    // created, so that the span can record it, and it is echoed in the response.
+    router
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(|request: &http::Request<_>| {
+                    // Log the matched route's path (with placeholders not filled in).
+                    // Use request.uri() or OriginalUri if you want the real path.
+                    let path = request.uri().path();
+                    let matched_path = request
+                        .extensions()
+                        .get::<MatchedPath>()
+                        .map(MatchedPath::as_str)
+                        .filter(|&matched_path| matched_path != path);
+                    let request_id = request
+                        .headers()
+                        .get(REQUEST_ID_HEADER)
+                        .and_then(|value| value.to_str().ok());
+                    let span = info_span!(
+                        "http_request",
+                        method = ?request.method(),
+                        matched_path,
+                        path,
+                        request_id,
+                        // Recorded by the `User` extractor, if the request is authenticated.
+                        user_id = tracing::field::Empty,
+                        status = tracing::field::Empty,
+                        latency_ms = tracing::field::Empty,
+                    );
+                    // Continue the trace of the caller, if it sent a `traceparent` header.
+                    let parent_context = global::get_text_map_propagator(|propagator| {
+                        propagator.extract(&HeaderExtractor(request.headers()))
+                    });
+                    span.set_parent(parent_context);
+                    span
+                })
+                .on_response(
+                    |response: &http::Response<_>, latency: Duration, span: &Span| {
+                        let status = response.status().as_u16();
+                        let latency_ms = latency.as_millis() as u64;
+                        span.record("status", status);
+                        span.record("latency_ms", latency_ms);
+                        info!(status, latency_ms, "request completed");
+                    },
+                )
+                .on_failure(
+                    |failure: ServerErrorsFailureClass, latency: Duration, span: &Span| {
+                        let latency_ms = latency.as_millis() as u64;
+                        span.record("latency_ms", latency_ms);
+                        error!(%failure, latency_ms, "request failed");
+                    },
+                ),
+        )
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+}
+
+#[cfg(test)]
+mod tests {
+    use ::axum::http::{HeaderValue, Method};
+    use ::axum::routing::get;
+    use ::futures_util::future::BoxFuture;
+    use ::opentelemetry::trace::{SpanKind, TracerProvider as _};
+    use ::opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+    use ::opentelemetry_sdk::trace::TracerProvider;
+    use ::std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::http_client::tests::client;
+    use crate::http_client::{Endpoint, Upstream};
+    use crate::test_support::serve;
+
+    const TRACED: Endpoint = Endpoint {
+        name: "traced",
+        upstream: Upstream::GitHubApi,
+        method: Method::GET,
+        path: "/traced",
+    };
+
+    /// The trace of a caller, as in the example of the W3C Trace Context specification
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const CALLER_SPAN_ID: &str = "00f067aa0ba902b7";
+
+    /// Keeps the exported spans, in place of an OTLP collector.
+    #[derive(Clone, Debug, Default)]
+    struct InMemoryExporter(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for InMemoryExporter {
+        fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+            self.0.lock().unwrap().extend(batch);
+            Box::pin(std::future::ready(Ok(())))
+        }
+    }
+
+    /// Serves `/traced`, which records the `traceparent` header of every call, and returns its
+    /// base URL and the recorded headers.
+    async fn fake_upstream() -> (String, Arc<Mutex<Vec<Option<HeaderValue>>>>) {
+        let traceparents = Arc::new(Mutex::new(Vec::new()));
+        let handler = {
+            let traceparents = traceparents.clone();
+            move |headers: HeaderMap| async move {
+                let traceparent = headers.get("traceparent").cloned();
+                traceparents.lock().unwrap().push(traceparent);
+            }
+        };
+        let router = Router::new().route("/traced", get(handler));
+        let url = format!("http://{}/", serve(router).await);
+        (url, traceparents)
+    }
+
+    #[tokio::test]
+    async fn calls_continue_the_trace_of_the_caller() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let exporter = InMemoryExporter::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let _tracing = ::tracing::subscriber::set_default(
+            tracing_subscriber::registry()
+                .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("tests"))),
+        );
+
+        let (upstream_url, traceparents) = fake_upstream().await;
+        let github = client(upstream_url.parse().unwrap());
+        let router = Router::new().route(
+            "/call",
+            get(move || async move {
+                github.send(&TRACED, |request| request).await.unwrap();
+            }),
+        );
+        let server_url = format!("http://{}/", serve(wrap_router(router)).await);
+        let response = reqwest::Client::new()
+            .get(format!("{server_url}call"))
+            .header("traceparent", format!("00-{TRACE_ID}-{CALLER_SPAN_ID}-01"))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+
+        // The upstream is called in the trace of our caller, on behalf of a span of ours.
+        let traceparents = traceparents.lock().unwrap().clone();
+        let [Some(traceparent)] = &traceparents[..] else {
+            panic!("expected one call with a traceparent, got {traceparents:?}");
+        };
+        let traceparent = traceparent.to_str().unwrap();
+        let [version, trace_id, parent_span_id, flags] =
+            traceparent.split('-').collect::<Vec<_>>()[..]
+        else {
+            panic!("malformed traceparent {traceparent}");
+        };
+        assert_eq!((version, trace_id, flags), ("00", TRACE_ID, "01"));
+        assert_ne!(parent_span_id, CALLER_SPAN_ID);
+
+        // That span is exported, as the client side of the call.
+        for result in provider.force_flush() {
+            result.unwrap();
+        }
+        let spans = exporter.0.lock().unwrap();
+        let outbound = spans
+            .iter()
+            .find(|span| span.name == "outbound_request")
+            .expect("the outbound request is exported");
+        assert_eq!(outbound.span_context.trace_id().to_string(), TRACE_ID);
+        assert_eq!(outbound.span_context.span_id().to_string(), parent_span_id);
+        assert_eq!(outbound.span_kind, SpanKind::Client);
+    }
 }
@@ -1,5 +1,8 @@
 #!/usr/bin/env bash
 # Launches a dev-server for local development.
+#
+# Ctrl+C (or SIGTERM) stops all three processes gracefully: each one gets SIGTERM, and the script
+# waits until they have exited.
 
 set -e
 
@@ -7,20 +10,32 @@ cd "$(dirname "$0")"
 
 cargo build -p dev_server
 
+# Each `$!` below is the pid of the process itself rather than of a subshell, so that it receives
+# the signals.
 pushd ui
 # The `elm-land server` command, by default, listens on port 1234.
-( elm-land server | awk '{print "elm: " $0}' 2>&1 ) &
+elm-land server > >(awk '{print "elm: " $0}') 2>&1 &
 elm_server_pid=$!
 popd
 
 # The `cargo shuttle run` command, by default, listens on port 8000.
-( cargo shuttle run | awk '{print "rust: " $0}' 2>&1 ) &
+cargo shuttle run > >(awk '{print "rust: " $0}') 2>&1 &
 rust_server_pid=$!
 
+./target/debug/dev_server > >(awk '{print "dev-server: " $0}') 2>&1 &
+dev_server_pid=$!
+
+stop() {
+    kill -TERM "$dev_server_pid" "$rust_server_pid" "$elm_server_pid" 2>/dev/null || true
+}
+# Background processes of a script ignore Ctrl+C, so it is forwarded as SIGTERM.
+trap stop INT TERM
+
 # `open` may only work on a Mac. The opened browser will not be able to connect to the locally
 # running server, until the servers have finished initializing.
 open http://localhost.home.ig:8080/
 
-cargo run -p dev_server | awk '{print "dev-server: " $0}' 2>&1
-kill "$rust_server_pid"
-kill "$elm_server_pid"
+# Returns when the dev-server exits, or when a signal has been trapped.
+wait "$dev_server_pid" || true
+stop
+wait
@@ -0,0 +1,23 @@
+module Api.Csrf exposing (headers)
+
+import Http
+
+
+{-| The header that the API requires on `POST`, `PUT` and `DELETE` requests (see
+`server/src/csrf.rs`). Pass `shared.csrfToken`, which the server puts into `index.html`:
+
+    Http.request
+        { method = "PUT"
+        , headers = Api.Csrf.headers shared.csrfToken
+        , ...
+        }
+
+-}
+headers : Maybe String -> List Http.Header
+headers csrfToken =
+    case csrfToken of
+        Just token ->
+            [ Http.header "X-CSRF-Token" token ]
+
+        Nothing ->
+            []
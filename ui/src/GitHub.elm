@@ -1,4 +1,4 @@
-module GitHub exposing (getUser, oAuthLoginUrl)
+module GitHub exposing (defaultClientId, getUser, oAuthLoginUrl)
 
 import Http
 import Json.Decode as Decode exposing (Decoder)
@@ -8,8 +8,8 @@ import Url.Builder exposing (string)
 import User exposing (UserData)
 
 
-oAuthLoginUrl : Url -> String
-oAuthLoginUrl myUrl =
+oAuthLoginUrl : String -> Url -> String
+oAuthLoginUrl clientId myUrl =
     Url.Builder.crossOrigin gitHubPrePath
         [ "login", "oauth", "authorize" ]
         [ string "client_id" clientId
@@ -34,6 +34,14 @@ getUser token webDataToMsg =
         }
 
 
+{-| The client ID of the production GitHub App. The server passes the one of its deployment in
+the flags.
+-}
+defaultClientId : String
+defaultClientId =
+    "Iv1.b5ba4dcd32da9063"
+
+
 decodeUserData : Decoder UserData
 decodeUserData =
     Decode.map
@@ -45,11 +53,6 @@ decodeUserData =
 -- Private helpers --
 
 
-clientId : String
-clientId =
-    "Iv1.b5ba4dcd32da9063"
-
-
 apiPrePath : String
 apiPrePath =
     "https://api.github.com"
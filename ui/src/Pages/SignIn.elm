@@ -37,6 +37,7 @@ page shared route =
 type alias Model =
     { myUrl : Url
     , githubAccessToken : Maybe String
+    , githubClientId : String
     , user : WebData UserData
     , message : Maybe String
     , receivedMsg : List Msg
@@ -54,6 +55,7 @@ initModel : Shared.Model -> Url -> Model
 initModel shared url =
     { myUrl = url
     , githubAccessToken = shared.githubAccessToken
+    , githubClientId = shared.githubClientId
     , user = shared.user
     , message = Nothing
     , receivedMsg = []
@@ -74,7 +76,7 @@ update msg m =
     in
     case msg of
         Login ->
-            ( model, Effect.loadExternalUrl <| GitHub.oAuthLoginUrl model.myUrl )
+            ( model, Effect.loadExternalUrl <| GitHub.oAuthLoginUrl model.githubClientId model.myUrl )
 
         GetUser ->
             updateRequestUser model
@@ -27,14 +27,18 @@ import Shared.Msg
 
 type alias Flags =
     { githubAccessToken : Maybe String
+    , githubClientId : Maybe String
+    , csrfToken : Maybe String
     }
 
 
 decoder : Json.Decode.Decoder Flags
 decoder =
-    Json.Decode.map
+    Json.Decode.map3
         Flags
         (Json.Decode.maybe <| Json.Decode.field "githubAccessToken" Json.Decode.string)
+        (Json.Decode.maybe <| Json.Decode.field "githubClientId" Json.Decode.string)
+        (Json.Decode.maybe <| Json.Decode.field "csrfToken" Json.Decode.string)
 
 
 
@@ -57,7 +61,7 @@ init flagsResult _ =
                     -- let
                     --     _ = Debug.log "Error decoding flags" (Json.Decode.errorToString err)
                     -- in
-                    { githubAccessToken = Nothing }
+                    { githubAccessToken = Nothing, githubClientId = Nothing, csrfToken = Nothing }
 
         ( model, effect ) =
             case flags.githubAccessToken of
@@ -69,7 +73,11 @@ init flagsResult _ =
                 Nothing ->
                     ( initModel, Effect.none )
     in
-    ( { model | githubAccessToken = flags.githubAccessToken }
+    ( { model
+        | githubAccessToken = flags.githubAccessToken
+        , githubClientId = Maybe.withDefault GitHub.defaultClientId flags.githubClientId
+        , csrfToken = flags.csrfToken
+      }
     , effect
     )
 
@@ -77,6 +85,8 @@ init flagsResult _ =
 initModel : Model
 initModel =
     { githubAccessToken = Nothing
+    , githubClientId = GitHub.defaultClientId
+    , csrfToken = Nothing
     , user = RemoteData.NotAsked
     }
 
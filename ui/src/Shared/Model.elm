@@ -13,5 +13,7 @@ own file, so they can be imported by `Effect.elm`
 -}
 type alias Model =
     { githubAccessToken : Maybe String
+    , githubClientId : String
+    , csrfToken : Maybe String
     , user : WebData UserData
     }
@@ -20,20 +20,135 @@ function getCookie(cname) {
 // The value returned here will be passed as flags
 // into your `Shared.init` function.
 export const flags = ({ _env }) => {
+  const config = readConfig();
+  const flags = {};
+  if (config.githubClientId) {
+    flags.githubClientId = config.githubClientId;
+  }
+  if (config.csrfToken) {
+    flags.csrfToken = config.csrfToken;
+  }
   const token = getCookie("github-access-token");
-  if (token == "") {
-    return {};
-  } else {
-    return {
-      githubAccessToken: token
-    };
+  if (token != "") {
+    flags.githubAccessToken = token;
   }
+  return flags;
 };
 
+// The server injects the configuration of its deployment into `index.html` (see
+// `server/src/route/spa/index_html.rs`). The elm-land dev server does not, so it may be missing.
+function readConfig() {
+  const element = document.getElementById("app-config");
+  try {
+    return element ? JSON.parse(element.textContent) : {};
+  } catch (_error) {
+    return {};
+  }
+}
+
 // This is called AFTER your Elm app starts up
 //
 // Here you can work with `app.ports` to send messages
 // to your Elm application, or subscribe to incoming
 // messages from Elm
-export const onReady = ({ _app, _env }) => {
+export const onReady = ({ app, _env }) => {
+  subscribeToServerEvents(app);
+  reportClientLogs(app, readConfig().csrfToken);
 };
+
+// Streams server events (`/api/events`) into Elm, if the Elm app declares these ports:
+//
+//     port subscribeToServerEvents : List String -> Cmd msg
+//     port receiveServerEvent : (Json.Decode.Value -> msg) -> Sub msg
+//
+// Sending a list of topics (re)opens the stream for exactly those topics. An empty list closes
+// it. Every event arrives as `{ eventId, topic, data }`. After a `resync` event, which has the
+// topic `null`, the Elm app should reload the data it is following.
+function subscribeToServerEvents(app) {
+  const ports = app.ports || {};
+  if (!ports.subscribeToServerEvents || !ports.receiveServerEvent) {
+    return;
+  }
+  let source = null;
+  ports.subscribeToServerEvents.subscribe((topics) => {
+    if (source) {
+      source.close();
+      source = null;
+    }
+    if (topics.length > 0) {
+      // `EventSource` reconnects on its own, and resumes with a `Last-Event-ID` header.
+      source = new EventSource("/api/events?topics=" + encodeURIComponent(topics.join(",")));
+      source.onmessage = (message) => ports.receiveServerEvent.send(JSON.parse(message.data));
+      source.addEventListener("resync", () =>
+        ports.receiveServerEvent.send({ eventId: null, topic: null, data: null })
+      );
+    }
+  });
+}
+
+// Reports errors to the server (`/api/client-logs`), where they end up in the log with the target
+// `client`. Uncaught errors are reported on their own. The Elm app may report more (e.g. HTTP or
+// decode errors) if it declares this port:
+//
+//     port reportClientLog : Json.Encode.Value -> Cmd msg
+//
+// with values like `{ level : "error", message : String, requestId : Maybe String }`. `requestId`
+// is the `X-Request-Id` of the server response that went wrong, if any.
+function reportClientLogs(app, csrfToken) {
+  const maxBatchSize = 20;
+  const flushDelayMillis = 2000;
+  let queue = [];
+  let timer = null;
+
+  const flush = (useBeacon) => {
+    clearTimeout(timer);
+    timer = null;
+    if (queue.length == 0) {
+      return;
+    }
+    const body = JSON.stringify({ events: queue.splice(0, queue.length) });
+    if (useBeacon && navigator.sendBeacon) {
+      navigator.sendBeacon("/api/client-logs", new Blob([body], { type: "application/json" }));
+    } else {
+      // Failures are not reported: that could loop forever. Beacons cannot carry the CSRF token,
+      // they pass as `Sec-Fetch-Site: same-origin` (see `server/src/csrf.rs`).
+      const headers = { "Content-Type": "application/json" };
+      if (csrfToken) {
+        headers["X-CSRF-Token"] = csrfToken;
+      }
+      fetch("/api/client-logs", {
+        method: "POST",
+        headers,
+        body,
+        keepalive: true
+      }).catch(() => {});
+    }
+  };
+
+  const report = ({ level, message, requestId }) => {
+    queue.push({
+      level: level || "error",
+      message: String(message).slice(0, 2000),
+      page: window.location.pathname,
+      userAgent: navigator.userAgent.slice(0, 500),
+      requestId: requestId || null
+    });
+    if (queue.length >= maxBatchSize) {
+      flush(false);
+    } else if (!timer) {
+      timer = setTimeout(() => flush(false), flushDelayMillis);
+    }
+  };
+
+  window.addEventListener("error", (event) =>
+    report({ message: `${event.message} (${event.filename}:${event.lineno})` })
+  );
+  window.addEventListener("unhandledrejection", (event) =>
+    report({ message: `Unhandled rejection: ${event.reason}` })
+  );
+  window.addEventListener("pagehide", () => flush(true));
+  const ports = app.ports || {};
+  if (ports.reportClientLog) {
+    ports.reportClientLog.subscribe(report);
+  }
+}